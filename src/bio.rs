@@ -1,13 +1,20 @@
-use crate::bio_format::bam::{from_bam_inner, from_sam_inner};
-use crate::bio_format::bcf::{from_bcf_inner, from_vcf_inner};
-use crate::bio_format::bed::from_bed_inner;
-use crate::bio_format::cram::from_cram_inner;
-use crate::bio_format::fasta::{from_fasta_inner, from_fastq_inner, nuon_to_fasta, nuon_to_fastq};
-use crate::bio_format::gfa::from_gfa_inner;
-use crate::bio_format::gff::from_gff_inner;
-use crate::bio_format::Compression;
-use nu_plugin::{EvaluatedCall, LabeledError};
-use nu_protocol::Value;
+use crate::bio_format::bam::{from_bam_inner, from_sam_inner, nuon_to_bam, nuon_to_sam};
+use crate::bio_format::bcf::{from_bcf_inner, from_vcf_inner, nuon_to_bcf, nuon_to_vcf, vcf_long};
+use crate::bio_format::bed::{from_bed_inner, nuon_to_bed};
+use crate::bio_format::cram::{from_cram_inner, nuon_to_cram};
+use crate::bio_format::fasta::{
+    faidx_inner, from_fasta_inner, from_fastq_inner, index_fasta_inner, nuon_to_fasta,
+    nuon_to_fastq,
+};
+use crate::bio_format::gfa::{from_gfa_inner, nuon_to_gfa};
+use crate::bio_format::gff::{from_gff_inner, nuon_to_gff};
+use crate::bio_format::gtf::from_gtf_inner;
+use crate::bio_format::query::query_inner;
+use crate::bio_format::{
+    header_and_body_output, input_reader, input_seekable_reader, into_list_stream, Compression,
+};
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{LabeledError, PipelineData, Value};
 
 /// We implement a bunch of parsers on the `Bio` struct.
 pub struct Bio;
@@ -17,12 +24,12 @@ impl Bio {
     pub fn from_fasta(
         &self,
         call: &EvaluatedCall,
-        input: &Value,
+        input: PipelineData,
         gz: Compression,
-    ) -> Result<Value, LabeledError> {
-        let value_records = from_fasta_inner(call, input, gz)?;
+    ) -> Result<PipelineData, LabeledError> {
+        let records = from_fasta_inner(call, input_reader(call, input)?, gz)?;
 
-        Ok(Value::list(value_records, call.head))
+        Ok(into_list_stream(records, call.head))
     }
 
     pub fn to_fasta(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
@@ -33,11 +40,11 @@ impl Bio {
     pub fn from_fastq(
         &self,
         call: &EvaluatedCall,
-        input: &Value,
+        input: PipelineData,
         gz: Compression,
-    ) -> Result<Value, LabeledError> {
-        let value_records = from_fastq_inner(call, input, gz)?;
-        Ok(Value::list(value_records, call.head))
+    ) -> Result<PipelineData, LabeledError> {
+        let records = from_fastq_inner(call, input_reader(call, input)?, gz)?;
+        Ok(into_list_stream(records, call.head))
     }
 
     /// Structured data to fastq
@@ -46,56 +53,162 @@ impl Bio {
     }
 
     /// These B(S)AM functions are quite slow at the moment.
-    pub fn from_bam(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-        from_bam_inner(call, input)
+    pub fn from_bam(
+        &self,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let (header, body) = from_bam_inner(call, input_reader(call, input)?)?;
+        header_and_body_output(call, header, body)
+    }
+    /// Structured data to BAM
+    pub fn to_bam(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_bam(call, input)
     }
+
     /// These B(S)AM functions are quite slow at the moment.
-    pub fn from_sam(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-        from_sam_inner(call, input)
+    pub fn from_sam(
+        &self,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let (header, body) = from_sam_inner(call, input_reader(call, input)?)?;
+        header_and_body_output(call, header, body)
+    }
+
+    /// Structured data to SAM
+    pub fn to_sam(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_sam(call, input)
+    }
+
+    /// Fetch regions of an indexed fasta.
+    pub fn faidx(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        let value_records = faidx_inner(call, input)?;
+        Ok(Value::list(value_records, call.head))
+    }
+
+    /// Index a fasta, writing the .fai (and .gzi) next to it.
+    pub fn index_fasta(&self, call: &EvaluatedCall) -> Result<Value, LabeledError> {
+        let value_records = index_fasta_inner(call)?;
+        Ok(Value::list(value_records, call.head))
+    }
+
+    /// Query an indexed file for regions.
+    pub fn query(&self, call: &EvaluatedCall) -> Result<Value, LabeledError> {
+        query_inner(call)
     }
 
     /// Parse a CRAM file.
-    pub fn from_cram(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-        from_cram_inner(call, input)
+    pub fn from_cram(
+        &self,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let (reader, length) = input_seekable_reader(call, input)?;
+        let (header, body) = from_cram_inner(call, reader, length)?;
+        header_and_body_output(call, header, body)
+    }
+
+    /// Structured data to CRAM
+    pub fn to_cram(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_cram(call, input)
     }
 
     /// Parse a BCF.
     pub fn from_bcf(
         &self,
         call: &EvaluatedCall,
-        input: &Value,
+        input: PipelineData,
         gz: Compression,
-    ) -> Result<Value, LabeledError> {
-        from_bcf_inner(call, input, gz)
+    ) -> Result<PipelineData, LabeledError> {
+        let (header, body) = from_bcf_inner(call, input_reader(call, input)?, gz)?;
+        header_and_body_output(call, header, body)
+    }
+
+    /// Structured data to BCF
+    pub fn to_bcf(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_bcf(call, input)
     }
     /// Parse a VCF.
     pub fn from_vcf(
+        &self,
+        call: &EvaluatedCall,
+        input: PipelineData,
+        gz: Compression,
+    ) -> Result<PipelineData, LabeledError> {
+        let (header, body) = from_vcf_inner(call, input_reader(call, input)?, gz)?;
+        header_and_body_output(call, header, body)
+    }
+
+    /// Structured data to VCF
+    pub fn to_vcf(
         &self,
         call: &EvaluatedCall,
         input: &Value,
         gz: Compression,
     ) -> Result<Value, LabeledError> {
-        from_vcf_inner(call, input, gz)
+        nuon_to_vcf(call, input, gz)
+    }
+
+    /// One row per variant and sample
+    pub fn vcf_long(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        vcf_long(call, input)
     }
 
     /// Parse a GFF.
-    pub fn from_gff(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-        let value_records = from_gff_inner(call, input)?;
-        Ok(Value::list(value_records, call.head))
+    pub fn from_gff(
+        &self,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let records = from_gff_inner(call, input_reader(call, input)?)?;
+        Ok(into_list_stream(records, call.head))
+    }
+
+    /// Structured data to GFF
+    pub fn to_gff(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_gff(call, input)
+    }
+
+    /// Parse a GTF.
+    pub fn from_gtf(
+        &self,
+        call: &EvaluatedCall,
+        input: PipelineData,
+        gz: Compression,
+    ) -> Result<PipelineData, LabeledError> {
+        let records = from_gtf_inner(call, input_reader(call, input)?, gz)?;
+        Ok(into_list_stream(records, call.head))
     }
 
     /// Parse a GFA.
     pub fn from_gfa(
         &self,
         call: &EvaluatedCall,
-        input: &Value,
+        input: PipelineData,
         gz: Compression,
-    ) -> Result<Value, LabeledError> {
-        from_gfa_inner(call, input, gz)
+    ) -> Result<PipelineData, LabeledError> {
+        let value = from_gfa_inner(call, input_reader(call, input)?, gz)?;
+        Ok(PipelineData::Value(value, None))
+    }
+
+    /// Structured data to GFA
+    pub fn to_gfa(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_gfa(call, input)
     }
 
     /// Parse a BED.
-    pub fn from_bed(&self, call: &EvaluatedCall, input: Value) -> Result<Value, LabeledError> {
-        from_bed_inner(call, input).map(|e| Value::list(e, call.head))
+    pub fn from_bed(
+        &self,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let records = from_bed_inner(call, input_reader(call, input)?)?;
+        Ok(into_list_stream(records, call.head))
+    }
+
+    /// Structured data to BED
+    pub fn to_bed(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_bed(call, input)
     }
 }
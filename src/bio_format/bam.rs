@@ -1,25 +1,55 @@
-use crate::bio_format::SpanExt;
+use crate::bio_format::query::find_index;
+use crate::bio_format::{Records, SpanExt};
 use noodles::{
     bam,
-    sam::{self, alignment::Record as SAMRecord, header::record::value::Map},
+    core::Region,
+    csi,
+    sam::{self, alignment::Record as SAMRecord},
+};
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{record, LabeledError, Record, Value};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, BufRead, Read},
+    path::Path,
+    str::FromStr,
 };
-use nu_plugin::{EvaluatedCall, LabeledError};
-use nu_protocol::{record, Record, Value};
 
 /// Columns in a BAM/SAM file
 pub const BAM_COLUMNS: &[&str] = &[
     "read_name",
     "flags",
-    "reference_sequence_id",
+    "reference_sequence_name",
     "alignment_start",
     "mapping_quality",
     "cigar",
-    "mate_reference_sequence_id",
+    "mate_reference_sequence_name",
     "mate_alignment_start",
     "template_length",
     "sequence",
     "quality_scores",
     "data",
+    "data_types",
+];
+
+/// Named SAM flags, as given by `--decode-flags`.
+const FLAG_NAMES: &[(&str, sam::record::Flags)] = &[
+    ("paired", sam::record::Flags::SEGMENTED),
+    ("proper_pair", sam::record::Flags::PROPERLY_ALIGNED),
+    ("unmapped", sam::record::Flags::UNMAPPED),
+    ("mate_unmapped", sam::record::Flags::MATE_UNMAPPED),
+    ("reverse", sam::record::Flags::REVERSE_COMPLEMENTED),
+    (
+        "mate_reverse",
+        sam::record::Flags::MATE_REVERSE_COMPLEMENTED,
+    ),
+    ("read1", sam::record::Flags::FIRST_SEGMENT),
+    ("read2", sam::record::Flags::LAST_SEGMENT),
+    ("secondary", sam::record::Flags::SECONDARY),
+    ("qc_fail", sam::record::Flags::QC_FAIL),
+    ("duplicate", sam::record::Flags::DUPLICATE),
+    ("supplementary", sam::record::Flags::SUPPLEMENTARY),
 ];
 
 /// Header fields in a B/SAM file
@@ -33,32 +63,27 @@ pub const HEADER_COLUMNS: &[&str] = &[
 
 /// Parse a B/SAM header
 pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
-    // @HD in SAM.
-    let header_op = h.header();
-    // unwrap to the default header whatever that is?
-    // should be able to modify it later to suit our needs.
-    let default_map = Map::default();
-    let header = header_op.unwrap_or(&default_map);
-
-    let header_nuon = Value::record(
-        record!(
-        "version" => call.head.with_string(header.version()),
-        // what's the default..?
-        // if it's no good, we can always map -> string
-        "sorting_order" => call.head.with_string(header.sort_order().unwrap_or_default()),
-        "grouping" => call.head.with_string(header.group_order().unwrap_or_default()),
-        "sub_sort_order" => call.head.with_string_or(header.subsort_order(), "No subsort order.")
+    // @HD in SAM, nothing when the file has no header line.
+    let header_nuon = match h.header() {
+        Some(header) => Value::record(
+            record!(
+            "version" => call.head.with_string(header.version()),
+            // nothing when absent, so `to sam` does not add them.
+            "sorting_order" => string_or_nothing(call, header.sort_order()),
+            "grouping" => string_or_nothing(call, header.group_order()),
+            "sub_sort_order" => call.head.with_string_or(header.subsort_order(), "No subsort order.")
+            ),
+            call.head,
         ),
-        call.head,
-    );
+        None => Value::nothing(call.head),
+    };
 
     // @SQ.
     let reference_sequences = h.reference_sequences();
-    let reference_sequences_nuon = Value::record(Record {
-        cols: reference_sequences.keys().map(|e| e.to_string()).collect(),
-        vals: reference_sequences
+    let reference_sequences_nuon = Value::record(
+        reference_sequences
             .iter()
-            .map(|(name, f)| Value::record(record! {
+            .map(|(name, f)| (name.to_string(), Value::record(record! {
                     "sequence_name" => call.head.with_string(name),
                     "sequence_length" => Value::int(usize::from(f.length()) as i64, call.head),
                     "alternate_locus" => call.head.with_string_or(f.alternative_locus(), "No alternative locus."),
@@ -70,18 +95,17 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
                     "molecule_topology" => call.head.with_string_or(f.molecule_topology(), "No molecule topology"),
                     "uri" => call.head.with_string_or(f.uri(), "No URI"),
                 }, call.head)
-            )
-            .collect() },
+            ))
+            .collect(),
         call.head
     );
 
     // @RG
     let read_groups = h.read_groups();
-    let read_groups_nuon = Value::record(Record {
-        cols: read_groups.keys().cloned().collect(),
-        vals: read_groups
+    let read_groups_nuon = Value::record(
+        read_groups
             .iter()
-            .map(|(id, f)| Value::record(record! {
+            .map(|(id, f)| (id.clone(), Value::record(record! {
                     "id" => call.head.with_string(id),
                     "barcode" => call.head.with_string_or(f.barcode(), "No barcode"),
                     "sequencing_center" => call.head.with_string_or(f.sequencing_center(), "No sequencing center"),
@@ -103,18 +127,17 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
                     "platform_unit" => call.head.with_string_or(f.platform_unit(), "No platform unit"),
                     "sample" => call.head.with_string_or(f.sample(), "No sample"),
                 }, call.head
-            ))
-            .collect() },
+            )))
+            .collect(),
          call.head,
     );
 
     // @PG
     let programs = h.programs();
-    let programs_nuon = Value::record(Record {
-        cols: programs.keys().cloned().collect(),
-        vals: programs
+    let programs_nuon = Value::record(
+        programs
             .iter()
-            .map(|(id, f)| Value::record(record! {
+            .map(|(id, f)| (id.clone(), Value::record(record! {
                     "id" => call.head.with_string(id),
                     "name" => call.head.with_string_or(f.name(), "No name"),
                     "command_line" => call.head.with_string_or(f.command_line(), "No command line"),
@@ -122,8 +145,8 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
                     "description" => call.head.with_string_or(f.description(), "No description"),
                     "version" => call.head.with_string_or(f.version(), "No version"),
     }, call.head
-            ))
-            .collect() },
+            )))
+            .collect(),
         call.head,
     );
 
@@ -146,56 +169,186 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
     )
 }
 
-/// Parse a SAM record, and append to a vector
-pub fn create_record_values(call: &EvaluatedCall, r: SAMRecord) -> Vec<Value> {
+/// An optional string column, with nothing when it is absent.
+fn string_or_nothing<S: ToString>(call: &EvaluatedCall, value: Option<S>) -> Value {
+    value
+        .map(|v| call.head.with_string(v))
+        .unwrap_or(Value::nothing(call.head))
+}
+
+/// An optional integer column, with nothing when it is absent.
+fn int_or_nothing<T: TryInto<i64>>(call: &EvaluatedCall, value: Option<T>) -> Value {
+    value
+        .and_then(|v| v.try_into().ok())
+        .map(|v| Value::int(v, call.head))
+        .unwrap_or(Value::nothing(call.head))
+}
+
+/// Turn the auxiliary data of a record into a nushell record keyed by
+/// tag. Values are typed by their SAM type: `A`, `Z` and `H` are strings,
+/// the integer types ints, `f` floats, and `B` arrays lists.
+fn data_to_record(call: &EvaluatedCall, data: &sam::record::Data) -> Value {
+    use sam::record::data::field::{value::Array, Value as DataValue};
+
+    fn list<T: Copy>(call: &EvaluatedCall, vals: &[T], f: impl Fn(T) -> Value) -> Value {
+        Value::list(vals.iter().map(|v| f(*v)).collect(), call.head)
+    }
+
+    let int = |i: i64| Value::int(i, call.head);
+    let float = |f: f32| Value::float(f.to_string().parse().unwrap_or(f as f64), call.head);
+
+    let record_inner = Record::from_iter(data.iter().map(|(tag, value)| {
+        let value = match value {
+            DataValue::Character(c) => call.head.with_string(char::from(*c)),
+            DataValue::Int8(i) => int(*i as i64),
+            DataValue::UInt8(i) => int(*i as i64),
+            DataValue::Int16(i) => int(*i as i64),
+            DataValue::UInt16(i) => int(*i as i64),
+            DataValue::Int32(i) => int(*i as i64),
+            DataValue::UInt32(i) => int(*i as i64),
+            DataValue::Float(f) => float(*f),
+            DataValue::String(s) => call.head.with_string(s),
+            DataValue::Hex(h) => call.head.with_string(h),
+            DataValue::Array(Array::Int8(vals)) => list(call, vals, |i| int(i as i64)),
+            DataValue::Array(Array::UInt8(vals)) => list(call, vals, |i| int(i as i64)),
+            DataValue::Array(Array::Int16(vals)) => list(call, vals, |i| int(i as i64)),
+            DataValue::Array(Array::UInt16(vals)) => list(call, vals, |i| int(i as i64)),
+            DataValue::Array(Array::Int32(vals)) => list(call, vals, |i| int(i as i64)),
+            DataValue::Array(Array::UInt32(vals)) => list(call, vals, |i| int(i as i64)),
+            DataValue::Array(Array::Float(vals)) => list(call, vals, float),
+        };
+        (tag.to_string(), value)
+    }));
+
+    Value::record(record_inner, call.head)
+}
+
+/// The SAM type of each auxiliary field, keyed by tag, so the `data`
+/// column can be written back with the same types: `A`, `c`, `C`, `s`,
+/// `S`, `i`, `I`, `f`, `Z`, `H`, or `B:` and the array subtype.
+fn data_types_to_record(call: &EvaluatedCall, data: &sam::record::Data) -> Value {
+    use sam::record::data::field::Value as DataValue;
+
+    let record_inner = Record::from_iter(data.iter().map(|(tag, value)| {
+        let ty = match value {
+            DataValue::Array(array) => format!("B:{}", array.subtype()),
+            other => other.ty().to_string(),
+        };
+        (tag.to_string(), call.head.with_string(ty))
+    }));
+
+    Value::record(record_inner, call.head)
+}
+
+/// Parse a SAM record, and append to a vector. Reference sequence IDs
+/// are resolved to their names in the header.
+pub fn create_record_values(
+    call: &EvaluatedCall,
+    header: &sam::Header,
+    r: SAMRecord,
+) -> Vec<Value> {
     let flags = r.flags().bits();
-    let mapping_quality = r
-        .mapping_quality()
-        .map(|m_q| format!("{}", u8::from(m_q)))
-        .unwrap_or_default();
     let sequence: Vec<u8> = r.sequence().as_ref().iter().map(|e| u8::from(*e)).collect();
 
+    let reference_sequence_name = |id: Option<usize>| {
+        id.and_then(|id| header.reference_sequences().get_index(id))
+            .map(|(name, _)| call.head.with_string(name))
+            .unwrap_or(Value::nothing(call.head))
+    };
+
+    let cigar = r
+        .cigar()
+        .iter()
+        .map(|op| {
+            Value::record(
+                record! {
+                    "op" => call.head.with_string(op.kind()),
+                    "len" => Value::int(op.len() as i64, call.head),
+                },
+                call.head,
+            )
+        })
+        .collect();
+
     vec![
-        call.head.with_string_or(r.read_name(), "No read name."),
-        call.head.with_string(format!("{:#06x}", flags)),
-        call.head
-            .with_string_or(r.reference_sequence_id(), "No reference sequence ID"),
-        call.head
-            .with_string_or(r.alignment_start(), "No alignment start"),
-        call.head.with_string(mapping_quality),
-        call.head.with_string(r.cigar()),
-        call.head.with_string_or(
-            r.mate_reference_sequence_id(),
-            "No mate reference sequence ID",
-        ),
-        call.head
-            .with_string_or(r.mate_alignment_start(), "No mate alignment start"),
-        call.head.with_string(r.template_length()),
+        r.read_name()
+            .map(|n| call.head.with_string(n))
+            .unwrap_or(Value::nothing(call.head)),
+        Value::int(flags as i64, call.head),
+        reference_sequence_name(r.reference_sequence_id()),
+        int_or_nothing(call, r.alignment_start().map(usize::from)),
+        int_or_nothing(call, r.mapping_quality().map(u8::from)),
+        Value::list(cigar, call.head),
+        reference_sequence_name(r.mate_reference_sequence_id()),
+        int_or_nothing(call, r.mate_alignment_start().map(usize::from)),
+        Value::int(r.template_length() as i64, call.head),
         call.head.with_string(String::from_utf8(sequence).unwrap()),
         call.head.with_string(r.quality_scores()),
-        call.head.with_string(r.data()),
+        data_to_record(call, r.data()),
+        data_types_to_record(call, r.data()),
     ]
 }
 
-/// Parse a BAM file into a nushell structure.
-pub fn from_bam_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val,
-        other => {
-            return Err(LabeledError {
-                label: "Input should be binary.".into(),
-                msg: format!("requires binary input, got {}", other.get_type()),
-                span: Some(call.head),
-            })
-        }
-    };
+/// Decode SAM flags into a record of named booleans.
+fn decode_flags(call: &EvaluatedCall, flags: sam::record::Flags) -> Value {
+    let record_inner = Record::from_iter(FLAG_NAMES.iter().map(|(name, flag)| {
+        (
+            name.to_string(),
+            Value::bool(flags.contains(*flag), call.head),
+        )
+    }));
+
+    Value::record(record_inner, call.head)
+}
+
+/// Make a row of the body from a record. With `--decode-flags`, a
+/// `decoded_flags` column follows `flags`.
+pub fn create_record(
+    call: &EvaluatedCall,
+    header: &sam::Header,
+    r: SAMRecord,
+) -> Result<Value, LabeledError> {
+    let decoded_flags = call
+        .has_flag("decode-flags")?
+        .then(|| decode_flags(call, r.flags()));
+
+    let mut cols: Vec<String> = BAM_COLUMNS.iter().map(|e| e.to_string()).collect();
+    let mut vals = create_record_values(call, header, r);
+
+    if let Some(decoded_flags) = decoded_flags {
+        cols.insert(2, "decoded_flags".into());
+        vals.insert(2, decoded_flags);
+    }
+
+    Ok(Value::record(
+        Record::from_iter(cols.into_iter().zip(vals)),
+        call.head,
+    ))
+}
+
+/// Make a row of the body from a record read from a SAM or BAM file.
+fn record_to_nuon(
+    call: &EvaluatedCall,
+    header: &sam::Header,
+    record: io::Result<SAMRecord>,
+) -> Result<Value, LabeledError> {
+    let r = record.map_err(|e| {
+        LabeledError::new("Record reading failed.")
+            .with_label(format!("cause of failure: {}", e), call.head)
+    })?;
 
-    let mut reader = bam::Reader::new(stream.as_slice());
-    let raw_header = reader.read_header().map_err(|err| LabeledError {
-        label: "Could not read header.".into(),
-        msg: format!("error reading header at {}", err),
-        span: Some(call.head),
+    create_record(call, header, r)
+}
+
+/// Parse a BAM file into its header, and its records, read lazily.
+pub fn from_bam_inner<R: Read + Send + 'static>(
+    call: &EvaluatedCall,
+    stream: R,
+) -> Result<(Value, Records), LabeledError> {
+    let mut reader = bam::Reader::new(stream);
+    let raw_header = reader.read_header().map_err(|err| {
+        LabeledError::new("Could not read header.")
+            .with_label(format!("error reading header at {}", err), call.head)
     })?;
 
     // TODO: better error handling here.
@@ -213,74 +366,621 @@ pub fn from_bam_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, Labe
         parse_header(call, &raw_header)
     };
 
-    let value_records = reader
-        .records(&raw_header)
-        .map(|record| {
-            let r = record.map_err(|e| LabeledError {
-                label: "Record reading failed.".into(),
-                msg: format!("cause of failure: {}", e),
-                span: Some(call.head),
-            })?;
-
-            let inner_record = Record::from_iter(
-                BAM_COLUMNS
-                    .iter()
-                    .map(|e| e.to_string())
-                    .zip(create_record_values(call, r)),
-            );
+    let call = call.clone();
+    let records = std::iter::from_fn(move || {
+        let record = reader.records(&raw_header).next()?;
+        Some(record_to_nuon(&call, &raw_header, record))
+    });
 
-            Ok(Value::record(inner_record, call.head))
-        })
-        .collect::<Result<Vec<_>, LabeledError>>()?;
+    Ok((header, Box::new(records)))
+}
+
+/// Whether a record overlaps a region, as the BAM query decides it.
+fn overlaps(header: &sam::Header, record: &SAMRecord, region: &Region) -> bool {
+    let id = header.reference_sequences().get_index_of(region.name());
+    match (
+        record.reference_sequence_id(),
+        record.alignment_start(),
+        record.alignment_end(),
+    ) {
+        (Some(record_id), Some(start), Some(end)) => {
+            Some(record_id) == id && region.interval().intersects((start..=end).into())
+        }
+        _ => false,
+    }
+}
+
+/// Query a sorted BAM file for records overlapping the regions, using
+/// its BAI or CSI index to seek to them. Records are returned in the order
+/// of the regions, and a record overlapping more than one region is only
+/// returned for the first of them.
+pub fn query_bam(
+    call: &EvaluatedCall,
+    path: &Path,
+    regions: &[Region],
+) -> Result<Value, LabeledError> {
+    let io_error = |context: String| {
+        move |e: std::io::Error| {
+            LabeledError::new("BAM query failed.").with_label(format!("{context}: {e}"), call.head)
+        }
+    };
+
+    let index_path = find_index(call, path, &[".bai", ".csi"])?;
+    let index = if index_path.extension().is_some_and(|ext| ext == "csi") {
+        csi::read(&index_path)
+    } else {
+        bam::bai::read(&index_path)
+    }
+    .map_err(io_error(format!(
+        "could not read index {}",
+        index_path.display()
+    )))?;
+
+    let mut reader = File::open(path)
+        .map(bam::Reader::new)
+        .map_err(io_error(format!("could not open {}", path.display())))?;
+    let header = reader
+        .read_header()
+        .map_err(io_error("could not read header".into()))?;
+
+    let mut value_records = Vec::new();
+
+    for (i, region) in regions.iter().enumerate() {
+        let query = reader
+            .query(&header, &index, region)
+            .map_err(io_error(format!("could not query region {region}")))?;
+
+        for record in query {
+            let r = record.map_err(io_error(format!(
+                "record reading failed in region {region}"
+            )))?;
+            if regions[..i].iter().any(|seen| overlaps(&header, &r, seen)) {
+                continue;
+            }
+            value_records.push(create_record(call, &header, r)?);
+        }
+    }
 
     Ok(Value::record(
         record! {
-            "header" => header,
+            "header" => parse_header(call, &header),
             "body" => Value::list(value_records, call.head)
         },
         call.head,
     ))
 }
 
-/// Parse a SAM file into a nushell structure.
-pub fn from_sam_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    // match on file type
-    // TODO: remove this unwrap
-    let stream = input.as_binary().unwrap();
-
+/// Parse a SAM file into its header, and its records, read lazily.
+pub fn from_sam_inner<R: BufRead + Send + 'static>(
+    call: &EvaluatedCall,
+    stream: R,
+) -> Result<(Value, Records), LabeledError> {
     let mut reader = sam::Reader::new(stream);
-    let header = reader.read_header().map_err(|err| LabeledError {
-        label: "Unable to parse SAM header".into(),
-        msg: format!("{}", err),
-        span: Some(call.head),
+    let header = reader.read_header().map_err(|err| {
+        LabeledError::new("Unable to parse SAM header").with_label(format!("{}", err), call.head)
     })?;
     let header_nuon = parse_header(call, &header);
 
-    let value_records = reader
-        .records(&header)
-        .map(|record| {
-            let r = record.map_err(|e| LabeledError {
-                label: "Record reading failed.".into(),
-                msg: format!("cause of failure: {}", e),
-                span: Some(call.head),
-            })?;
-
-            let inner_record = Record::from_iter(
-                BAM_COLUMNS
+    let call = call.clone();
+    let records = std::iter::from_fn(move || {
+        let record = reader.records(&header).next()?;
+        Some(record_to_nuon(&call, &header, record))
+    });
+
+    Ok((header_nuon, Box::new(records)))
+}
+
+/// Get a string out of a header record, treating the placeholder
+/// strings written by [`parse_header`] as missing.
+fn header_field(value: &Value, col: &str, placeholder: &str) -> Option<String> {
+    value
+        .get_data_by_key(col)
+        .filter(|v| !v.is_nothing())
+        .and_then(|v| v.coerce_string().ok())
+        .filter(|s| !s.is_empty() && s != placeholder)
+}
+
+/// Turn the header record produced by [`parse_header`] back into
+/// a [`sam::Header`].
+pub fn nuon_to_header(call: &EvaluatedCall, value: &Value) -> Result<sam::Header, LabeledError> {
+    let mut lines = Vec::new();
+
+    // @HD, only if the input had one.
+    if let Some(metadata) = value
+        .get_data_by_key(HEADER_COLUMNS[0])
+        .filter(|v| !v.is_nothing())
+    {
+        let mut line = String::from("@HD");
+        let fields = [
+            ("VN", "version", ""),
+            ("SO", "sorting_order", ""),
+            ("GO", "grouping", ""),
+            ("SS", "sub_sort_order", "No subsort order."),
+        ];
+        for (tag, col, placeholder) in fields {
+            if let Some(v) = header_field(&metadata, col, placeholder) {
+                line.push_str(&format!("\t{tag}:{v}"));
+            }
+        }
+        lines.push(line);
+    }
+
+    // @SQ
+    if let Some(Value::Record { val, .. }) = value.get_data_by_key(HEADER_COLUMNS[1]) {
+        for (name, f) in val.iter() {
+            let mut line = format!("@SQ\tSN:{name}");
+            let fields = [
+                ("LN", "sequence_length", ""),
+                ("AH", "alternate_locus", "No alternative locus."),
+                ("AN", "alternate_names", "No alternative names."),
+                ("AS", "assembly_id", "No assembly ID."),
+                ("DS", "description", "No description"),
+                ("M5", "md5", "No md5 checksum"),
+                ("SP", "species", "No species name"),
+                ("TP", "molecule_topology", "No molecule topology"),
+                ("UR", "uri", "No URI"),
+            ];
+            for (tag, col, placeholder) in fields {
+                if let Some(v) = header_field(f, col, placeholder) {
+                    line.push_str(&format!("\t{tag}:{v}"));
+                }
+            }
+            lines.push(line);
+        }
+    }
+
+    // @RG
+    if let Some(Value::Record { val, .. }) = value.get_data_by_key(HEADER_COLUMNS[2]) {
+        for (id, f) in val.iter() {
+            let mut line = format!("@RG\tID:{id}");
+            let fields = [
+                ("BC", "barcode", "No barcode"),
+                ("CN", "sequencing_center", "No sequencing center"),
+                ("DS", "description", "No description"),
+                ("FO", "flow_order", "No flow order"),
+                ("KS", "key_sequence", "No key sequence"),
+                ("LB", "library", "No library"),
+                ("PG", "program", "No program"),
+                ("PL", "platform", "No platform"),
+                // zero is what we write when there is no insert size.
+                ("PI", "predicted_insert_size", "0"),
+                ("PM", "platform_model", "No platform model"),
+                ("PU", "platform_unit", "No platform unit"),
+                ("SM", "sample", "No sample"),
+            ];
+            for (tag, col, placeholder) in fields {
+                if let Some(v) = header_field(f, col, placeholder) {
+                    line.push_str(&format!("\t{tag}:{v}"));
+                }
+            }
+            lines.push(line);
+        }
+    }
+
+    // @PG
+    if let Some(Value::Record { val, .. }) = value.get_data_by_key(HEADER_COLUMNS[3]) {
+        for (id, f) in val.iter() {
+            let mut line = format!("@PG\tID:{id}");
+            let fields = [
+                ("PN", "name", "No name"),
+                ("CL", "command_line", "No command line"),
+                ("PP", "previous_id", "No previous ID"),
+                ("DS", "description", "No description"),
+                ("VN", "version", "No version"),
+            ];
+            for (tag, col, placeholder) in fields {
+                if let Some(v) = header_field(f, col, placeholder) {
+                    line.push_str(&format!("\t{tag}:{v}"));
+                }
+            }
+            lines.push(line);
+        }
+    }
+
+    // @CO
+    if let Some(Value::List { vals, .. }) = value.get_data_by_key(HEADER_COLUMNS[4]) {
+        for comment in vals {
+            lines.push(format!("@CO\t{}", comment.coerce_string()?));
+        }
+    }
+
+    let mut raw_header = lines.join("\n");
+    raw_header.push('\n');
+
+    raw_header.parse().map_err(|e| {
+        LabeledError::new("Could not build SAM header.")
+            .with_label(format!("cause of failure: {}", e), call.head)
+    })
+}
+
+/// Parse a column of a body row, treating missing values and empty
+/// strings as absent.
+fn parse_column<T>(row: &Value, col: &str) -> Result<Option<T>, LabeledError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = match row.get_data_by_key(col).filter(|v| !v.is_nothing()) {
+        Some(v) => v.coerce_string()?,
+        None => return Ok(None),
+    };
+
+    if s.is_empty() {
+        return Ok(None);
+    }
+
+    s.parse().map(Some).map_err(|e| {
+        LabeledError::new(format!("Could not parse column `{col}`."))
+            .with_label(format!("value {s:?} is invalid: {e}"), row.span())
+    })
+}
+
+/// Look up a reference sequence name column in the header.
+fn parse_reference_sequence(
+    header: &sam::Header,
+    row: &Value,
+    col: &str,
+) -> Result<Option<usize>, LabeledError> {
+    let name: String = match parse_column(row, col)? {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+
+    header
+        .reference_sequences()
+        .get_index_of(name.as_str())
+        .map(Some)
+        .ok_or(
+            LabeledError::new(format!("Could not parse column `{col}`.")).with_label(
+                format!("reference sequence {name:?} is not in the header"),
+                row.span(),
+            ),
+        )
+}
+
+/// Turn the CIGAR column, a list of `{op, len}` records, back into a string.
+/// A string is used as is.
+fn format_cigar(row: &Value) -> Result<Option<String>, LabeledError> {
+    match row.get_data_by_key(BAM_COLUMNS[5]) {
+        Some(Value::List { vals, .. }) if !vals.is_empty() => {
+            let mut cigar = String::new();
+            for op in vals {
+                let get = |key: &str| {
+                    op.get_data_by_key(key).ok_or(
+                        LabeledError::new("Could not parse column `cigar`.").with_label(
+                            format!("CIGAR operations need an `op` and a `len`, missing {key}"),
+                            op.span(),
+                        ),
+                    )
+                };
+                cigar.push_str(&get("len")?.as_int()?.to_string());
+                cigar.push_str(&get("op")?.coerce_string()?);
+            }
+            Ok(Some(cigar))
+        }
+        Some(Value::String { val, .. }) if !val.is_empty() => Ok(Some(val)),
+        _ => Ok(None),
+    }
+}
+
+/// Turn a typed integer list into the smallest `B` array which holds it.
+fn ints_to_array(ints: Vec<i64>) -> Option<sam::record::data::field::value::Array> {
+    use sam::record::data::field::value::Array;
+
+    let (min, max) = (
+        ints.iter().copied().min().unwrap_or(0),
+        ints.iter().copied().max().unwrap_or(0),
+    );
+    fn convert<T: TryFrom<i64>>(ints: &[i64]) -> Result<Vec<T>, T::Error> {
+        ints.iter().map(|i| T::try_from(*i)).collect()
+    }
+
+    if min >= 0 && max <= u8::MAX as i64 {
+        convert(&ints).ok().map(Array::UInt8)
+    } else if min >= i8::MIN as i64 && max <= i8::MAX as i64 {
+        convert(&ints).ok().map(Array::Int8)
+    } else if min >= 0 && max <= u16::MAX as i64 {
+        convert(&ints).ok().map(Array::UInt16)
+    } else if min >= i16::MIN as i64 && max <= i16::MAX as i64 {
+        convert(&ints).ok().map(Array::Int16)
+    } else if min >= 0 && max <= u32::MAX as i64 {
+        convert(&ints).ok().map(Array::UInt32)
+    } else {
+        convert(&ints).ok().map(Array::Int32)
+    }
+}
+
+/// Convert a nushell value to an auxiliary field of the given SAM type,
+/// as listed in the `data_types` column.
+fn typed_data_value(value: &Value, ty: &str) -> Result<sam::record::data::field::Value, String> {
+    use sam::record::data::field::{value::Array, Value as DataValue};
+
+    fn int<T: TryFrom<i64>>(value: &Value, ty: &str) -> Result<T, String> {
+        let i = value
+            .as_int()
+            .map_err(|_| format!("type {ty} needs an int, got {}", value.get_type()))?;
+        T::try_from(i).map_err(|_| format!("{i} does not fit in type {ty}"))
+    }
+
+    fn ints<T: TryFrom<i64>>(value: &Value, ty: &str) -> Result<Vec<T>, String> {
+        match value {
+            Value::List { vals, .. } => vals.iter().map(|v| int(v, ty)).collect(),
+            other => Err(format!("type {ty} needs a list, got {}", other.get_type())),
+        }
+    }
+
+    let float = |v: &Value| {
+        v.as_f64()
+            .map(|f| f as f32)
+            .map_err(|_| format!("type {ty} needs a float, got {}", v.get_type()))
+    };
+    let string = |v: &Value| {
+        v.coerce_string()
+            .map_err(|_| format!("type {ty} needs a string, got {}", v.get_type()))
+    };
+
+    Ok(match ty {
+        "A" => {
+            let s = string(value)?;
+            match s.as_bytes() {
+                [c] => DataValue::Character((*c).try_into().map_err(|e| format!("{e}"))?),
+                _ => return Err(format!("type A needs a single character, got {s:?}")),
+            }
+        }
+        "c" => DataValue::Int8(int(value, ty)?),
+        "C" => DataValue::UInt8(int(value, ty)?),
+        "s" => DataValue::Int16(int(value, ty)?),
+        "S" => DataValue::UInt16(int(value, ty)?),
+        "i" => DataValue::Int32(int(value, ty)?),
+        "I" => DataValue::UInt32(int(value, ty)?),
+        "f" => DataValue::Float(float(value)?),
+        "Z" => DataValue::String(string(value)?),
+        "H" => DataValue::Hex(string(value)?.parse().map_err(|e| format!("{e}"))?),
+        "B:c" => DataValue::Array(Array::Int8(ints(value, ty)?)),
+        "B:C" => DataValue::Array(Array::UInt8(ints(value, ty)?)),
+        "B:s" => DataValue::Array(Array::Int16(ints(value, ty)?)),
+        "B:S" => DataValue::Array(Array::UInt16(ints(value, ty)?)),
+        "B:i" => DataValue::Array(Array::Int32(ints(value, ty)?)),
+        "B:I" => DataValue::Array(Array::UInt32(ints(value, ty)?)),
+        "B:f" => match value {
+            Value::List { vals, .. } => DataValue::Array(Array::Float(
+                vals.iter().map(float).collect::<Result<_, _>>()?,
+            )),
+            other => return Err(format!("type {ty} needs a list, got {}", other.get_type())),
+        },
+        other => return Err(format!("unknown SAM type {other:?}")),
+    })
+}
+
+/// Turn the `data` column made by [`data_to_record`] back into SAM data.
+/// Fields are written with their type in the `data_types` column, if it
+/// has one. Otherwise ints take the smallest integer type which holds
+/// them, as samtools does, and strings are `Z`. A string is parsed as tab
+/// separated fields.
+fn nuon_to_data(row: &Value) -> Result<Option<sam::record::Data>, LabeledError> {
+    use sam::record::data::field::{value::Array, Tag, Value as DataValue};
+
+    let val = match row.get_data_by_key(BAM_COLUMNS[11]) {
+        Some(Value::Record { val, .. }) => val,
+        _ => return parse_column(row, BAM_COLUMNS[11]),
+    };
+    let types = row.get_data_by_key(BAM_COLUMNS[12]);
+
+    let error = |tag: &str, msg: String| {
+        LabeledError::new(format!("Could not parse tag `{tag}` in column `data`."))
+            .with_label(msg, row.span())
+    };
+
+    let mut data = sam::record::Data::default();
+
+    for (tag, value) in val.iter() {
+        let data_tag: Tag = tag.parse().map_err(|e| error(tag, format!("{e}")))?;
+
+        let ty = types
+            .as_ref()
+            .and_then(|t| t.get_data_by_key(tag))
+            .filter(|t| !t.is_nothing());
+        if let Some(ty) = ty {
+            let data_value =
+                typed_data_value(value, &ty.coerce_string()?).map_err(|e| error(tag, e))?;
+            data.insert(data_tag, data_value);
+            continue;
+        }
+
+        let data_value = match value {
+            Value::Int { val, .. } => match i32::try_from(*val) {
+                Ok(i) => DataValue::from(i),
+                Err(_) => u32::try_from(*val)
+                    .map(DataValue::from)
+                    .map_err(|_| error(tag, format!("{val} does not fit in 32 bits")))?,
+            },
+            Value::Float { val, .. } => DataValue::Float(*val as f32),
+            Value::String { val, .. } => DataValue::String(val.clone()),
+            Value::List { vals, .. } if vals.iter().any(|v| matches!(v, Value::Float { .. })) => {
+                let floats = vals
+                    .iter()
+                    .map(|v| v.as_f64().map(|f| f as f32))
+                    .collect::<Result<Vec<_>, _>>()?;
+                DataValue::Array(Array::Float(floats))
+            }
+            Value::List { vals, .. } => {
+                let ints = vals
                     .iter()
-                    .map(|e| e.to_string())
-                    .zip(create_record_values(call, r)),
-            );
+                    .map(|v| v.as_int())
+                    .collect::<Result<Vec<_>, _>>()?;
+                let array = ints_to_array(ints)
+                    .ok_or_else(|| error(tag, "array values do not fit in 32 bits".into()))?;
+                DataValue::Array(array)
+            }
+            other => {
+                return Err(error(
+                    tag,
+                    format!(
+                        "values can be ints, floats, strings or lists, got {}",
+                        other.get_type()
+                    ),
+                ))
+            }
+        };
 
-            Ok(Value::record(inner_record, call.head))
-        })
+        data.insert(data_tag, data_value);
+    }
+
+    Ok(Some(data))
+}
+
+/// Turn a row with [`BAM_COLUMNS`] back into a SAM record.
+pub fn nuon_to_record(header: &sam::Header, row: &Value) -> Result<SAMRecord, LabeledError> {
+    let mut builder = SAMRecord::builder();
+
+    if let Some(read_name) = parse_column(row, BAM_COLUMNS[0])? {
+        builder = builder.set_read_name(read_name);
+    }
+
+    // flags are an integer, `decoded_flags` is only for reading.
+    if let Some(flags) = parse_column::<u16>(row, BAM_COLUMNS[1])? {
+        builder = builder.set_flags(sam::record::Flags::from(flags));
+    }
+
+    if let Some(id) = parse_reference_sequence(header, row, BAM_COLUMNS[2])? {
+        builder = builder.set_reference_sequence_id(id);
+    }
+
+    if let Some(start) = parse_column(row, BAM_COLUMNS[3])? {
+        builder = builder.set_alignment_start(start);
+    }
+
+    if let Some(mapping_quality) = parse_column(row, BAM_COLUMNS[4])? {
+        builder = builder.set_mapping_quality(mapping_quality);
+    }
+
+    if let Some(cigar) = format_cigar(row)? {
+        let cigar = cigar.parse().map_err(|e| {
+            LabeledError::new("Could not parse column `cigar`.")
+                .with_label(format!("CIGAR {cigar:?} is invalid: {e}"), row.span())
+        })?;
+        builder = builder.set_cigar(cigar);
+    }
+
+    if let Some(id) = parse_reference_sequence(header, row, BAM_COLUMNS[6])? {
+        builder = builder.set_mate_reference_sequence_id(id);
+    }
+
+    if let Some(start) = parse_column(row, BAM_COLUMNS[7])? {
+        builder = builder.set_mate_alignment_start(start);
+    }
+
+    if let Some(template_length) = parse_column(row, BAM_COLUMNS[8])? {
+        builder = builder.set_template_length(template_length);
+    }
+
+    if let Some(sequence) = parse_column(row, BAM_COLUMNS[9])? {
+        builder = builder.set_sequence(sequence);
+    }
+
+    if let Some(quality_scores) = parse_column(row, BAM_COLUMNS[10])? {
+        builder = builder.set_quality_scores(quality_scores);
+    }
+
+    if let Some(data) = nuon_to_data(row)? {
+        builder = builder.set_data(data);
+    }
+
+    Ok(builder.build())
+}
+
+/// Split the `{header, body}` record shared by the B/SAM and CRAM
+/// parsers into a SAM header and its records.
+pub fn nuon_to_header_and_records(
+    call: &EvaluatedCall,
+    input: &Value,
+) -> Result<(sam::Header, Vec<SAMRecord>), LabeledError> {
+    let (header_nuon, body) = match (
+        input.get_data_by_key("header"),
+        input.get_data_by_key("body"),
+    ) {
+        (Some(h), Some(b)) => (h, b),
+        _ => {
+            return Err(
+                LabeledError::new("Input should be a record with a header and a body.").with_label(
+                    format!(
+                        "requires the output of `from sam` or `from bam`, got {}",
+                        input.get_type()
+                    ),
+                    call.head,
+                ),
+            )
+        }
+    };
+
+    let header = nuon_to_header(call, &header_nuon)?;
+    let records = body
+        .as_list()?
+        .iter()
+        .map(|row| nuon_to_record(&header, row))
         .collect::<Result<Vec<_>, LabeledError>>()?;
 
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head)
-        },
-        call.head,
-    ))
+    Ok((header, records))
+}
+
+/// Go from a parsed nuon B/SAM structure to a SAM string.
+pub fn nuon_to_sam(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let (header, records) = nuon_to_header_and_records(call, input)?;
+
+    let mut writer = sam::Writer::new(Vec::new());
+
+    writer.write_header(&header).map_err(|err| {
+        LabeledError::new("Error in writing SAM header").with_label(err.to_string(), call.head)
+    })?;
+
+    for record in records {
+        writer.write_record(&header, &record).map_err(|err| {
+            LabeledError::new(format!(
+                "Error in writing record ({}) to SAM",
+                record
+                    .read_name()
+                    .map(|n| n.to_string())
+                    .unwrap_or_default()
+            ))
+            .with_label(err.to_string(), call.head)
+        })?;
+    }
+
+    let out_final = String::from_utf8(writer.into_inner()).map_err(|err| {
+        LabeledError::new("Can't format bytes as UTF-8").with_label(err.to_string(), call.head)
+    })?;
+
+    Ok(Value::string(out_final, call.head))
+}
+
+/// Go from a parsed nuon B/SAM structure to BGZF-compressed BAM bytes.
+pub fn nuon_to_bam(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let (header, records) = nuon_to_header_and_records(call, input)?;
+
+    let mut writer = bam::Writer::new(Vec::new());
+
+    writer.write_header(&header).map_err(|err| {
+        LabeledError::new("Error in writing BAM header").with_label(err.to_string(), call.head)
+    })?;
+
+    for record in records {
+        writer.write_record(&header, &record).map_err(|err| {
+            LabeledError::new(format!(
+                "Error in writing record ({}) to BAM",
+                record
+                    .read_name()
+                    .map(|n| n.to_string())
+                    .unwrap_or_default()
+            ))
+            .with_label(err.to_string(), call.head)
+        })?;
+    }
+
+    // writes the BGZF EOF marker.
+    let out_final = writer.into_inner().finish().map_err(|err| {
+        LabeledError::new("Error in finishing the BAM stream")
+            .with_label(err.to_string(), call.head)
+    })?;
+
+    Ok(Value::binary(out_final, call.head))
 }
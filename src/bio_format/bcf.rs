@@ -1,26 +1,27 @@
 /// The VCF format
 use noodles::{
     bcf::{self, header::StringMaps},
-    bgzf, vcf,
+    bgzf,
+    vcf::{self, header::record::value::Collection},
 };
-use nu_plugin::{EvaluatedCall, LabeledError};
-use nu_protocol::{record, Record, Value};
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{record, LabeledError, Record, Value};
 
-use crate::bio_format::Compression;
-use std::io::{BufRead, BufReader};
+use crate::bio_format::{Compression, Records};
+use std::io::{self, BufRead, BufReader, Read, Write};
 
 use super::SpanExt;
 
 /// Compression status of a VCF reader.
-enum VCFReader<'a> {
-    Uncompressed(Box<vcf::Reader<&'a [u8]>>),
-    Compressed(Box<vcf::Reader<BufReader<bgzf::Reader<&'a [u8]>>>>),
+enum VCFReader<R> {
+    Uncompressed(Box<vcf::Reader<R>>),
+    Compressed(Box<vcf::Reader<BufReader<bgzf::Reader<R>>>>),
 }
 
 /// Compression status of a BCF reader.
-enum BCFReader<'a> {
-    Uncompressed(Box<bcf::Reader<bgzf::Reader<&'a [u8]>>>),
-    Compressed(Box<bcf::Reader<bgzf::Reader<bgzf::Reader<&'a [u8]>>>>),
+enum BCFReader<R> {
+    Uncompressed(Box<bcf::Reader<bgzf::Reader<R>>>),
+    Compressed(Box<bcf::Reader<bgzf::Reader<bgzf::Reader<R>>>>),
 }
 
 /// VCF column headers
@@ -49,8 +50,22 @@ const HEADER_COLUMNS: &[&str] = &[
     // "meta",
     // "pedigree",
     "samples",
+    "other",
 ];
 
+/// A structured header record, with the nonstandard fields of its
+/// definition (e.g. `Source` or `Version`) after the standard ones.
+fn header_record<'a, K: ToString + 'a>(
+    call: &EvaluatedCall,
+    mut record_inner: Record,
+    other_fields: impl IntoIterator<Item = (&'a K, &'a String)>,
+) -> Value {
+    for (tag, v) in other_fields {
+        record_inner.push(tag.to_string(), call.head.with_string(v));
+    }
+    Value::record(record_inner, call.head)
+}
+
 /// This parses the header of a V/BCF
 fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
     let file_format = call.head.with_string(h.file_format());
@@ -58,13 +73,14 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
 
     let infos_inner = Record::from_iter(infos.keys().map(|e| e.to_string()).zip(
         infos.values().map(|f| {
-            Value::record(
+            header_record(
+                call,
                 record! {
                 "number" => call.head.with_string(f.number()),
                 "type" => call.head.with_string(f.ty()),
                 "description" => call.head.with_string(f.description()),
                 },
-                call.head,
+                f.other_fields(),
             )
         }),
     ));
@@ -77,12 +93,13 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
 
     let filters_inner = Record::from_iter(filters.keys().map(|e| e.to_string()).zip(
         filters.values().map(|f| {
-            Value::record(
+            header_record(
+                call,
                 record! {
                       "description" => call.head.with_string(f.description())
 
                 },
-                call.head,
+                f.other_fields(),
             )
         }),
     ));
@@ -94,13 +111,14 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
 
     let formats_inner = Record::from_iter(formats.keys().map(|e| e.to_string()).zip(
         formats.values().map(|f| {
-            Value::record(
+            header_record(
+                call,
                 record! {
                     "number" => call.head.with_string(f.number()),
                     "type" => call.head.with_string(f.ty()),
                     "description" => call.head.with_string(f.description())
                 },
-                call.head,
+                f.other_fields(),
             )
         }),
     ));
@@ -112,11 +130,12 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
 
     let alt_alleles_inner = Record::from_iter(alt_alleles.keys().map(|e| e.to_string()).zip(
         alt_alleles.values().map(|f| {
-            Value::record(
+            header_record(
+                call,
                 record! {
                     "description" => call.head.with_string(f.description())
                 },
-                call.head,
+                f.other_fields(),
             )
         }),
     ));
@@ -135,7 +154,7 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
 
             vals.extend(f.other_fields().values().map(|e| call.head.with_string(e)));
 
-            let contig_vals_inner = Record::from_iter(cols.into_iter().zip(vals.into_iter()));
+            let contig_vals_inner = Record::from_iter(cols.into_iter().zip(vals));
 
             Value::record(contig_vals_inner, call.head)
         }),
@@ -143,7 +162,28 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
 
     let contigs_nuon = Value::record(contigs_inner, call.head);
 
-    // metadata, assembly, and pedigree are not currently parsed.
+    // every other record, e.g. `##reference` or `##bcftools_callCommand`,
+    // as a list of values by key. Structured values are records.
+    let others_inner = Record::from_iter(h.other_records().iter().map(|(key, collection)| {
+        let vals = match collection {
+            Collection::Unstructured(vals) => {
+                vals.iter().map(|v| call.head.with_string(v)).collect()
+            }
+            Collection::Structured(maps) => maps
+                .iter()
+                .map(|(id, map)| {
+                    let mut record_inner = record! { "ID" => call.head.with_string(id) };
+                    for (tag, v) in map.other_fields() {
+                        record_inner.push(tag.to_string(), call.head.with_string(v));
+                    }
+                    Value::record(record_inner, call.head)
+                })
+                .collect(),
+        };
+        (key.to_string(), Value::list(vals, call.head))
+    }));
+
+    let others_nuon = Value::record(others_inner, call.head);
 
     // sample names
     let sample_names_nuon = Value::list(
@@ -154,7 +194,6 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
         call.head,
     );
 
-    // TODO: I've skipped other records for the moment.
     // return the big record
     Value::record(
         Record::from_iter(HEADER_COLUMNS.iter().map(|e| e.to_string()).zip(vec![
@@ -165,14 +204,180 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
             alt_alleles_nuon,
             contigs_nuon,
             sample_names_nuon,
+            others_nuon,
         ])),
         call.head,
     )
 }
 
+/// Convert a VCF float to a nushell float, going through its string
+/// representation so `0.1` does not become `0.10000000149011612`.
+fn float_value(call: &EvaluatedCall, f: f32) -> Value {
+    Value::float(f.to_string().parse().unwrap_or(f as f64), call.head)
+}
+
+/// Turn a list of optional VCF values into a nushell list, with
+/// missing values as nothing.
+fn optional_list<T>(call: &EvaluatedCall, vals: &[Option<T>], f: impl Fn(&T) -> Value) -> Value {
+    Value::list(
+        vals.iter()
+            .map(|v| v.as_ref().map(&f).unwrap_or(Value::nothing(call.head)))
+            .collect(),
+        call.head,
+    )
+}
+
+/// BCF stores a single value the same way whatever the Number, so make
+/// sure values whose header Number is not 0 or 1 are always lists.
+fn list_by_number(
+    call: &EvaluatedCall,
+    value: Value,
+    number: Option<vcf::header::Number>,
+) -> Value {
+    let is_list = number.is_some_and(|n| !matches!(n, vcf::header::Number::Count(0 | 1)));
+
+    match value {
+        Value::List { .. } | Value::Nothing { .. } => value,
+        v if is_list => Value::list(vec![v], call.head),
+        v => v,
+    }
+}
+
+/// Turn the INFO of a record into a nushell record. The values are typed by
+/// noodles from the header INFO definitions (Number and Type): integers,
+/// floats, flags as booleans, and fields with a Number other than 0 or 1
+/// (e.g. A, R or .) as lists.
+fn info_to_record(call: &EvaluatedCall, header: &vcf::Header, info: &vcf::record::Info) -> Value {
+    use vcf::record::info::field::{value::Array, Value as InfoValue};
+
+    let record_inner = Record::from_iter(info.keys().zip(info.values()).map(|(key, value)| {
+        let value = match value {
+            None => Value::nothing(call.head),
+            Some(InfoValue::Integer(i)) => Value::int(*i as i64, call.head),
+            Some(InfoValue::Float(f)) => float_value(call, *f),
+            Some(InfoValue::Flag) => Value::bool(true, call.head),
+            Some(InfoValue::Character(c)) => call.head.with_string(c),
+            Some(InfoValue::String(s)) => call.head.with_string(s),
+            Some(InfoValue::Array(Array::Integer(vals))) => {
+                optional_list(call, vals, |i| Value::int(*i as i64, call.head))
+            }
+            Some(InfoValue::Array(Array::Float(vals))) => {
+                optional_list(call, vals, |f| float_value(call, *f))
+            }
+            Some(InfoValue::Array(Array::Character(vals))) => {
+                optional_list(call, vals, |c| call.head.with_string(c))
+            }
+            Some(InfoValue::Array(Array::String(vals))) => {
+                optional_list(call, vals, |s| call.head.with_string(s))
+            }
+        };
+
+        let number = header.infos().get(key).map(|def| def.number());
+        (key.to_string(), list_by_number(call, value, number))
+    }));
+
+    Value::record(record_inner, call.head)
+}
+
+/// Parse a GT value into its allele indices (nothing for a missing allele)
+/// and whether it is phased, which is nothing for a haploid call as there
+/// is nothing to phase. Unparseable values are kept as a string.
+fn genotype_to_record(call: &EvaluatedCall, s: &str) -> Value {
+    use vcf::record::genotypes::sample::value::{genotype::allele::Phasing, Genotype};
+
+    match s.parse::<Genotype>() {
+        Ok(genotype) => Value::record(
+            record! {
+                "alleles" => Value::list(
+                    genotype
+                        .iter()
+                        .map(|a| match a.position() {
+                            Some(i) => Value::int(i as i64, call.head),
+                            None => Value::nothing(call.head),
+                        })
+                        .collect(),
+                    call.head,
+                ),
+                "phased" => match genotype.len() {
+                    0 | 1 => Value::nothing(call.head),
+                    _ => Value::bool(
+                        genotype.iter().all(|a| a.phasing() == Phasing::Phased),
+                        call.head,
+                    ),
+                },
+            },
+            call.head,
+        ),
+        Err(_) => call.head.with_string(s),
+    }
+}
+
+/// Turn the genotypes of a record into a nushell record keyed by sample
+/// name. Each sample is a record of its FORMAT fields, typed by noodles
+/// from the header FORMAT definitions, with GT parsed by
+/// [`genotype_to_record`].
+fn genotypes_to_record(
+    call: &EvaluatedCall,
+    header: &vcf::Header,
+    genotypes: &vcf::record::Genotypes,
+) -> Value {
+    use vcf::record::genotypes::{
+        keys::key,
+        sample::{value::Array, Value as SampleValue},
+    };
+
+    let keys = genotypes.keys();
+
+    let samples = header
+        .sample_names()
+        .iter()
+        .zip(genotypes.values())
+        .map(|(name, sample)| {
+            let fields = Record::from_iter(keys.iter().enumerate().map(|(i, key)| {
+                let value = match sample.values().get(i).and_then(|v| v.as_ref()) {
+                    None => Value::nothing(call.head),
+                    Some(SampleValue::String(s)) if *key == key::GENOTYPE => {
+                        genotype_to_record(call, s)
+                    }
+                    Some(SampleValue::Integer(i)) => Value::int(*i as i64, call.head),
+                    Some(SampleValue::Float(f)) => float_value(call, *f),
+                    Some(SampleValue::Character(c)) => call.head.with_string(c),
+                    Some(SampleValue::String(s)) => call.head.with_string(s),
+                    Some(SampleValue::Array(Array::Integer(vals))) => {
+                        optional_list(call, vals, |i| Value::int(*i as i64, call.head))
+                    }
+                    Some(SampleValue::Array(Array::Float(vals))) => {
+                        optional_list(call, vals, |f| float_value(call, *f))
+                    }
+                    Some(SampleValue::Array(Array::Character(vals))) => {
+                        optional_list(call, vals, |c| call.head.with_string(c))
+                    }
+                    Some(SampleValue::Array(Array::String(vals))) => {
+                        optional_list(call, vals, |s| call.head.with_string(s))
+                    }
+                };
+
+                let number = header
+                    .formats()
+                    .get(key)
+                    .filter(|_| *key != key::GENOTYPE)
+                    .map(|def| def.number());
+                (key.to_string(), list_by_number(call, value, number))
+            }));
+
+            (name.clone(), Value::record(fields, call.head))
+        });
+
+    Value::record(Record::from_iter(samples), call.head)
+}
+
 /// Add a VCF record to the vector.
-/// TODO: make data more structured, so less is turned into a string immediately.
-fn add_record(call: &EvaluatedCall, r: vcf::Record, vec_vals: &mut Vec<Value>) {
+fn add_record(
+    call: &EvaluatedCall,
+    header: &vcf::Header,
+    r: vcf::Record,
+    vec_vals: &mut Vec<Value>,
+) {
     let pos = usize::from(r.position());
     let rlen = r.reference_bases().len();
 
@@ -185,16 +390,16 @@ fn add_record(call: &EvaluatedCall, r: vcf::Record, vec_vals: &mut Vec<Value>) {
         call.head.with_string(r.reference_bases()),
         call.head.with_string(r.alternate_bases()),
         call.head.with_string_or(r.filters(), ""),
-        call.head.with_string(r.info()),
-        call.head.with_string(r.genotypes()),
+        info_to_record(call, header, r.info()),
+        genotypes_to_record(call, header, r.genotypes()),
     ];
 
     vec_vals.extend_from_slice(&values_to_extend);
 }
 
 /// Read a BCF header and return the header, stringmaps, and also the header in nuon format.
-fn read_bcf_header(
-    reader: &mut BCFReader,
+fn read_bcf_header<R: Read>(
+    reader: &mut BCFReader<R>,
     call: &EvaluatedCall,
 ) -> Result<(vcf::Header, StringMaps, Value), LabeledError> {
     // avoid repetitive code
@@ -205,11 +410,8 @@ fn read_bcf_header(
         let raw_header = match r.read_header() {
             Ok(e) => e,
             Err(e) => {
-                return Err(LabeledError {
-                    label: "Could not read header.".into(),
-                    msg: format!("header unreadable due to {}", e),
-                    span: Some(call.head),
-                })
+                return Err(LabeledError::new("Could not read header.")
+                    .with_label(format!("header unreadable due to {}", e), call.head))
             }
         };
 
@@ -226,92 +428,72 @@ fn read_bcf_header(
     }
 }
 
-/// Generic function for optional compression to iterate over the BCF records.
-fn iterate_bcf_records<R: BufRead>(
-    mut reader: bcf::Reader<R>,
-    header: vcf::Header,
-    _string_maps: StringMaps,
+/// Make a row of the body from a VCF or BCF record.
+fn vcf_record_to_nuon(
     call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
-) -> Result<(), LabeledError> {
-    for record in reader.records(&header) {
-        let r = match record {
-            Ok(rec) => rec,
-            Err(e) => {
-                return Err(LabeledError {
-                    label: "Record reading failed.".into(),
-                    msg: format!("cause of failure: {}", e),
-                    span: Some(call.head),
-                })
-            }
-        };
+    header: &vcf::Header,
+    record: io::Result<vcf::Record>,
+) -> Result<Value, LabeledError> {
+    let r = match record {
+        Ok(rec) => rec,
+        Err(e) => {
+            return Err(LabeledError::new("Record reading failed.")
+                .with_label(format!("cause of failure: {}", e), call.head))
+        }
+    };
 
-        let mut vec_vals = Vec::new();
-        add_record(call, r, &mut vec_vals);
+    let mut vec_vals = Vec::new();
+    add_record(call, header, r, &mut vec_vals);
 
-        let record_inner =
-            Record::from_iter(VCF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+    let record_inner = Record::from_iter(VCF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
 
-        value_records.push(Value::record(record_inner, call.head))
-    }
+    Ok(Value::record(record_inner, call.head))
+}
 
-    Ok(())
+/// Generic function for optional compression to iterate lazily over the
+/// BCF records.
+fn iterate_bcf_records<R: BufRead + Send + 'static>(
+    mut reader: bcf::Reader<R>,
+    header: vcf::Header,
+    _string_maps: StringMaps,
+    call: EvaluatedCall,
+) -> Records {
+    Box::new(std::iter::from_fn(move || {
+        let record = reader.records(&header).next()?;
+        Some(vcf_record_to_nuon(&call, &header, record))
+    }))
 }
 
-/// Parse a fasta file into a nushell structure.
-pub fn from_bcf_inner(
+/// Parse a BCF file into its header, and its records, read lazily.
+pub fn from_bcf_inner<R: Read + Send + 'static>(
     call: &EvaluatedCall,
-    input: &Value,
+    stream: R,
     gz: Compression,
-) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val,
-        other => {
-            return Err(LabeledError {
-                label: "Input should be binary.".into(),
-                msg: format!("requires binary input, got {}", other.get_type()),
-                span: Some(call.head),
-            })
-        }
-    };
-
+) -> Result<(Value, Records), LabeledError> {
     let mut reader = match gz {
-        Compression::Uncompressed => {
-            BCFReader::Uncompressed(Box::new(bcf::Reader::new(stream.as_slice())))
-        }
+        Compression::Uncompressed => BCFReader::Uncompressed(Box::new(bcf::Reader::new(stream))),
         Compression::Gzipped => {
-            let gz = bgzf::Reader::new(stream.as_slice());
+            let gz = bgzf::Reader::new(stream);
             BCFReader::Compressed(Box::new(bcf::Reader::new(gz)))
         }
     };
 
-    let (header, string_maps, header_nuon) = read_bcf_header(&mut reader, call).unwrap();
+    let (header, string_maps, header_nuon) = read_bcf_header(&mut reader, call)?;
 
-    let mut value_records = Vec::new();
+    let call = call.clone();
 
     // now match on compression
-    match reader {
-        BCFReader::Uncompressed(uc) => {
-            iterate_bcf_records(*uc, header, string_maps, call, &mut value_records).unwrap();
-        }
-        BCFReader::Compressed(c) => {
-            iterate_bcf_records(*c, header, string_maps, call, &mut value_records).unwrap();
-        }
-    }
+    let records = match reader {
+        BCFReader::Uncompressed(uc) => iterate_bcf_records(*uc, header, string_maps, call),
+        BCFReader::Compressed(c) => iterate_bcf_records(*c, header, string_maps, call),
+    };
 
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head),
-        },
-        call.head,
-    ))
+    Ok((header_nuon, records))
 }
 
 /// Read a VCF header and return the header, stringmaps, and also the header in nuon format.
-fn read_vcf_header(
-    reader: &mut VCFReader,
+fn read_vcf_header<R: BufRead>(
+    reader: &mut VCFReader<R>,
     call: &EvaluatedCall,
 ) -> Result<(vcf::Header, Value), LabeledError> {
     // avoid repetitive code
@@ -323,11 +505,8 @@ fn read_vcf_header(
         let raw_header = match r.read_header() {
             Ok(rh) => rh,
             Err(e) => {
-                return Err(LabeledError {
-                    label: "Failed to read raw VCF header.".into(),
-                    msg: format!("cause of failure: {}", e),
-                    span: Some(call.head),
-                })
+                return Err(LabeledError::new("Failed to read raw VCF header.")
+                    .with_label(format!("cause of failure: {}", e), call.head))
             }
         };
 
@@ -342,85 +521,597 @@ fn read_vcf_header(
     }
 }
 
-/// Generic function for optional compression to iterate over the VCF records.
-fn iterate_vcf_records<R: BufRead>(
+/// Generic function for optional compression to iterate lazily over the
+/// VCF records.
+fn iterate_vcf_records<R: BufRead + Send + 'static>(
     mut reader: vcf::Reader<R>,
     header: vcf::Header,
+    call: EvaluatedCall,
+) -> Records {
+    Box::new(std::iter::from_fn(move || {
+        let record = reader.records(&header).next()?;
+        Some(vcf_record_to_nuon(&call, &header, record))
+    }))
+}
+
+/// Parse a VCF file into its header, and its records, read lazily.
+pub fn from_vcf_inner<R: BufRead + Send + 'static>(
     call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
-) -> Result<(), LabeledError> {
-    for record in reader.records(&header) {
-        let r = match record {
-            Ok(rec) => rec,
-            Err(e) => {
-                return Err(LabeledError {
-                    label: "Record reading failed.".into(),
-                    msg: format!("cause of failure: {}", e),
-                    span: Some(call.head),
-                })
+    stream: R,
+    gz: Compression,
+) -> Result<(Value, Records), LabeledError> {
+    let mut reader = match gz {
+        Compression::Uncompressed => VCFReader::Uncompressed(Box::new(vcf::Reader::new(stream))),
+        Compression::Gzipped => {
+            let gz = bgzf::Reader::new(stream);
+            VCFReader::Compressed(Box::new(vcf::Reader::new(BufReader::new(gz))))
+        }
+    };
+
+    let (header, header_nuon) = read_vcf_header(&mut reader, call)?;
+
+    let call = call.clone();
+
+    // now match on compression
+    let records = match reader {
+        VCFReader::Uncompressed(uc) => iterate_vcf_records(*uc, header, call),
+        VCFReader::Compressed(c) => iterate_vcf_records(*c, header, call),
+    };
+
+    Ok((header_nuon, records))
+}
+
+/// Get a string out of a record, with missing values as an empty string.
+fn column_string(value: &Value, col: &str) -> Result<String, LabeledError> {
+    match value.get_data_by_key(col) {
+        Some(v) if !v.is_nothing() => Ok(v.coerce_string()?),
+        _ => Ok(String::new()),
+    }
+}
+
+/// Format a single typed VCF value, with nothing as missing and
+/// lists comma separated.
+fn format_vcf_value(value: &Value) -> Result<String, LabeledError> {
+    match value {
+        Value::Nothing { .. } => Ok(".".into()),
+        Value::List { vals, .. } => Ok(vals
+            .iter()
+            .map(format_vcf_value)
+            .collect::<Result<Vec<_>, _>>()?
+            .join(",")),
+        other => Ok(other.coerce_string()?),
+    }
+}
+
+/// Format the INFO column, either the record made by [`info_to_record`],
+/// or a string which is used as is.
+fn format_info(value: Option<Value>) -> Result<String, LabeledError> {
+    let val = match value {
+        Some(Value::Record { val, .. }) => val,
+        Some(v) if !v.is_nothing() => return Ok(v.coerce_string()?),
+        _ => return Ok(".".into()),
+    };
+
+    let mut fields = Vec::new();
+    for (key, v) in val.iter() {
+        match v {
+            Value::Bool { val: true, .. } => fields.push(key.clone()),
+            Value::Bool { val: false, .. } => (),
+            other => fields.push(format!("{key}={}", format_vcf_value(other)?)),
+        }
+    }
+
+    Ok(if fields.is_empty() {
+        ".".into()
+    } else {
+        fields.join(";")
+    })
+}
+
+/// Format a GT record made by [`genotype_to_record`] back into a string.
+fn format_genotype(value: &Value) -> Result<String, LabeledError> {
+    let alleles = match value.get_data_by_key("alleles") {
+        Some(Value::List { vals, .. }) => vals,
+        _ => return Ok(value.coerce_string()?),
+    };
+    let phased = match value.get_data_by_key("phased") {
+        Some(p) if !p.is_nothing() => p.as_bool()?,
+        _ => false,
+    };
+
+    Ok(alleles
+        .iter()
+        .map(format_vcf_value)
+        .collect::<Result<Vec<_>, _>>()?
+        .join(if phased { "|" } else { "/" }))
+}
+
+/// Format the FORMAT and sample columns from the record made by
+/// [`genotypes_to_record`], or a string which is used as is. Samples are
+/// written in header order, and missing fields as a dot.
+fn format_genotypes(
+    header: &vcf::Header,
+    value: Option<Value>,
+) -> Result<Option<String>, LabeledError> {
+    let val = match value {
+        Some(Value::Record { val, .. }) => val,
+        Some(v) if !v.is_nothing() => {
+            let s = v.coerce_string()?;
+            return Ok((!s.trim().is_empty()).then_some(s));
+        }
+        _ => return Ok(None),
+    };
+
+    // FORMAT keys in order of appearance, with GT first as it must be.
+    let mut keys: Vec<String> = Vec::new();
+    for sample in val.values() {
+        if let Value::Record { val: fields, .. } = sample {
+            for key in fields.columns() {
+                if !keys.contains(key) {
+                    keys.push(key.clone());
+                }
             }
-        };
+        }
+    }
+    if let Some(i) = keys.iter().position(|k| k == "GT") {
+        let gt = keys.remove(i);
+        keys.insert(0, gt);
+    }
+
+    if keys.is_empty() {
+        return Ok(None);
+    }
 
-        let mut vec_vals = Vec::new();
-        add_record(call, r, &mut vec_vals);
+    let mut columns = vec![keys.join(":")];
+    for name in header.sample_names() {
+        let sample = val.get(name);
 
-        let vec_vals_inner =
-            Record::from_iter(VCF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+        let fields = keys
+            .iter()
+            .map(|key| match sample.and_then(|s| s.get_data_by_key(key)) {
+                Some(v @ Value::Record { .. }) if key == "GT" => format_genotype(&v),
+                Some(v) => format_vcf_value(&v),
+                None => Ok(".".into()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
-        value_records.push(Value::record(vec_vals_inner, call.head))
+        columns.push(fields.join(":"));
     }
 
-    Ok(())
+    Ok(Some(columns.join("\t")))
 }
 
-/// Parse a fasta file into a nushell structure.
-pub fn from_vcf_inner(
+/// Quote and escape a structured header value.
+fn quote_header_value(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Turn the header record produced by [`parse_header`] back into
+/// a [`vcf::Header`].
+fn nuon_to_vcf_header(call: &EvaluatedCall, value: &Value) -> Result<vcf::Header, LabeledError> {
+    let mut lines = vec![format!(
+        "##fileformat={}",
+        column_string(value, HEADER_COLUMNS[0])?
+    )];
+
+    // INFO, FILTER, FORMAT and ALT all share the same structure.
+    let structured = [
+        ("INFO", HEADER_COLUMNS[1]),
+        ("FILTER", HEADER_COLUMNS[2]),
+        ("FORMAT", HEADER_COLUMNS[3]),
+        ("ALT", HEADER_COLUMNS[4]),
+    ];
+
+    for (key, col) in structured {
+        if let Some(Value::Record { val, .. }) = value.get_data_by_key(col) {
+            for (id, f) in val.iter() {
+                let mut line = format!("##{key}=<ID={id}");
+                for (field, name) in [("Number", "number"), ("Type", "type")] {
+                    if f.get_data_by_key(name).is_some() {
+                        line.push_str(&format!(",{field}={}", column_string(f, name)?));
+                    }
+                }
+                line.push_str(&format!(
+                    ",Description={}",
+                    quote_header_value(&column_string(f, "description")?)
+                ));
+                if let Value::Record { val: fields, .. } = f {
+                    let standard = ["number", "type", "description"];
+                    for (k, v) in fields
+                        .iter()
+                        .filter(|(k, _)| !standard.contains(&k.as_str()))
+                    {
+                        line.push_str(&format!(",{k}={}", quote_header_value(&v.coerce_string()?)));
+                    }
+                }
+                line.push('>');
+                lines.push(line);
+            }
+        }
+    }
+
+    // contigs
+    if let Some(Value::Record { val, .. }) = value.get_data_by_key(HEADER_COLUMNS[5]) {
+        for (id, f) in val.iter() {
+            let mut line = format!("##contig=<ID={id}");
+            if let Value::Record { val: fields, .. } = f {
+                for (k, v) in fields.iter() {
+                    let v = v.coerce_string()?;
+                    // a length of zero is what we write when there is no length.
+                    if k == "length" && v == "0" {
+                        continue;
+                    }
+                    if v.contains([',', ' ', '"']) {
+                        line.push_str(&format!(",{k}={}", quote_header_value(&v)));
+                    } else {
+                        line.push_str(&format!(",{k}={v}"));
+                    }
+                }
+            }
+            line.push('>');
+            lines.push(line);
+        }
+    }
+
+    // other records, unstructured strings or structured records.
+    if let Some(Value::Record { val, .. }) = value.get_data_by_key(HEADER_COLUMNS[7]) {
+        for (key, vals) in val.iter() {
+            for v in vals.as_list()? {
+                match v {
+                    Value::Record { val: fields, .. } => {
+                        let id = column_string(v, "ID")?;
+                        let mut line = format!("##{key}=<ID={id}");
+                        for (k, f) in fields.iter().filter(|(k, _)| *k != "ID") {
+                            line.push_str(&format!(
+                                ",{k}={}",
+                                quote_header_value(&f.coerce_string()?)
+                            ));
+                        }
+                        line.push('>');
+                        lines.push(line);
+                    }
+                    other => lines.push(format!("##{key}={}", other.coerce_string()?)),
+                }
+            }
+        }
+    }
+
+    let mut columns = String::from("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO");
+    if let Some(Value::List { vals, .. }) = value.get_data_by_key(HEADER_COLUMNS[6]) {
+        if !vals.is_empty() {
+            columns.push_str("\tFORMAT");
+        }
+        for sample in vals {
+            columns.push('\t');
+            columns.push_str(&sample.coerce_string()?);
+        }
+    }
+    lines.push(columns);
+
+    let mut raw_header = lines.join("\n");
+    raw_header.push('\n');
+
+    raw_header.parse().map_err(|e| {
+        LabeledError::new("Could not build VCF header.")
+            .with_label(format!("cause of failure: {}", e), call.head)
+    })
+}
+
+/// Turn a row with [`VCF_COLUMNS`] back into a VCF record.
+fn nuon_to_vcf_record(
+    call: &EvaluatedCall,
+    header: &vcf::Header,
+    row: &Value,
+) -> Result<vcf::Record, LabeledError> {
+    // missing values are a dot in VCF.
+    let field = |col: &str| -> Result<String, LabeledError> {
+        let s = column_string(row, col)?;
+        Ok(if s.is_empty() { ".".into() } else { s })
+    };
+
+    let mut line = [
+        field(VCF_COLUMNS[0])?,
+        field(VCF_COLUMNS[1])?,
+        field(VCF_COLUMNS[4])?,
+        field(VCF_COLUMNS[5])?,
+        field(VCF_COLUMNS[6])?,
+        field(VCF_COLUMNS[3])?,
+        field(VCF_COLUMNS[7])?,
+        format_info(row.get_data_by_key(VCF_COLUMNS[8]))?,
+    ]
+    .join("\t");
+
+    if let Some(genotypes) = format_genotypes(header, row.get_data_by_key(VCF_COLUMNS[9]))? {
+        line.push('\t');
+        line.push_str(&genotypes);
+    }
+
+    let mut record = vcf::Record::try_from((header, line.as_str())).map_err(|e| {
+        LabeledError::new("Could not build VCF record.")
+            .with_label(format!("record {line:?} is invalid: {e}"), call.head)
+    })?;
+    pad_empty_samples(&mut record);
+
+    Ok(record)
+}
+
+/// noodles parses a sample of only `.` as having no values, and writes
+/// that back as an empty column, so give such samples one missing value.
+fn pad_empty_samples(record: &mut vcf::Record) {
+    use vcf::record::Genotypes;
+
+    let genotypes = record.genotypes();
+    if genotypes.keys().is_empty() || genotypes.values().all(|s| !s.values().is_empty()) {
+        return;
+    }
+
+    let values = genotypes
+        .values()
+        .map(|sample| match sample.values() {
+            [] => vec![None],
+            values => values.to_vec(),
+        })
+        .collect();
+
+    *record.genotypes_mut() = Genotypes::new(genotypes.keys().clone(), values);
+}
+
+/// Split the `{header, body}` record shared by the V/BCF parsers
+/// into a VCF header and its records.
+fn nuon_to_vcf_header_and_records(
+    call: &EvaluatedCall,
+    input: &Value,
+) -> Result<(vcf::Header, Vec<vcf::Record>), LabeledError> {
+    let (header_nuon, body) = match (
+        input.get_data_by_key("header"),
+        input.get_data_by_key("body"),
+    ) {
+        (Some(h), Some(b)) => (h, b),
+        _ => {
+            return Err(
+                LabeledError::new("Input should be a record with a header and a body.").with_label(
+                    format!(
+                        "requires the output of `from vcf` or `from bcf`, got {}",
+                        input.get_type()
+                    ),
+                    call.head,
+                ),
+            )
+        }
+    };
+
+    let header = nuon_to_vcf_header(call, &header_nuon)?;
+    let records = body
+        .as_list()?
+        .iter()
+        .map(|row| nuon_to_vcf_record(call, &header, row))
+        .collect::<Result<Vec<_>, LabeledError>>()?;
+
+    Ok((header, records))
+}
+
+/// Go from a parsed nuon V/BCF structure to a VCF, optionally
+/// BGZF-compressed.
+pub fn nuon_to_vcf(
     call: &EvaluatedCall,
     input: &Value,
     gz: Compression,
 ) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input.as_binary() {
-        Ok(s) => s,
-        Err(e) => {
-            return Err(LabeledError {
-                label: "Could not stream input as binary.".into(),
-                msg: format!("cause of failure: {}", e),
-                span: Some(call.head),
-            })
+    let (header, records) = nuon_to_vcf_header_and_records(call, input)?;
+
+    // avoid repetitive code
+    fn write_vcf<W: Write>(
+        writer: &mut vcf::Writer<W>,
+        header: &vcf::Header,
+        records: Vec<vcf::Record>,
+    ) -> std::io::Result<()> {
+        writer.write_header(header)?;
+        for record in records {
+            writer.write_record(header, &record)?;
         }
+        Ok(())
+    }
+
+    let write_error = |err: std::io::Error| {
+        LabeledError::new("Error in writing VCF").with_label(err.to_string(), call.head)
     };
 
-    let mut reader = match gz {
-        Compression::Uncompressed => VCFReader::Uncompressed(Box::new(vcf::Reader::new(stream))),
+    match gz {
+        Compression::Uncompressed => {
+            let mut writer = vcf::Writer::new(Vec::new());
+            write_vcf(&mut writer, &header, records).map_err(write_error)?;
+
+            let out_final = String::from_utf8(writer.into_inner()).map_err(|err| {
+                LabeledError::new("Can't format bytes as UTF-8")
+                    .with_label(err.to_string(), call.head)
+            })?;
+
+            Ok(Value::string(out_final, call.head))
+        }
         Compression::Gzipped => {
-            let gz = bgzf::Reader::new(stream);
-            VCFReader::Compressed(Box::new(vcf::Reader::new(BufReader::new(gz))))
+            let mut writer = vcf::Writer::new(bgzf::Writer::new(Vec::new()));
+            write_vcf(&mut writer, &header, records).map_err(write_error)?;
+
+            // writes the BGZF EOF marker.
+            let out_final = writer.into_inner().finish().map_err(write_error)?;
+
+            Ok(Value::binary(out_final, call.head))
         }
+    }
+}
+
+/// Get the GT values of a record ready for the BCF writer, which can not
+/// encode a missing GT value, so these are written as a missing allele,
+/// `.`, which reads back the same. It also pads a genotype only after its
+/// first allele, so genotypes with more than one allele must all have the
+/// same ploidy.
+fn bcf_genotypes(
+    call: &EvaluatedCall,
+    header: &vcf::Header,
+    record: &mut vcf::Record,
+) -> Result<(), LabeledError> {
+    use vcf::record::genotypes::{keys::key, sample::Value as SampleValue, Genotypes};
+
+    let genotypes = record.genotypes();
+    let gt = match genotypes.keys().get_index_of(&key::GENOTYPE) {
+        Some(i) => i,
+        None => return Ok(()),
     };
 
-    let (header, header_nuon) = match read_vcf_header(&mut reader, call) {
-        Ok(h) => h,
-        Err(e) => return Err(e),
+    let values: Vec<_> = genotypes
+        .values()
+        .map(|sample| {
+            let mut values = sample.values().to_vec();
+            if values.len() <= gt {
+                values.resize(gt + 1, None);
+            }
+            values[gt].get_or_insert(SampleValue::String(".".into()));
+            values
+        })
+        .collect();
+
+    let ploidy = |values: &[Option<SampleValue>]| match &values[gt] {
+        Some(SampleValue::String(s)) => s.matches(['/', '|']).count() + 1,
+        _ => 1,
     };
+    let max_ploidy = values.iter().map(|v| ploidy(v)).max().unwrap_or(1);
 
-    let mut value_records = Vec::new();
+    for (name, values) in header.sample_names().iter().zip(&values) {
+        let n = ploidy(values);
+        if n > 1 && n < max_ploidy {
+            return Err(
+                LabeledError::new("Genotypes of mixed ploidy can not be written to BCF.")
+                    .with_label(
+                        format!(
+                            "record at {}:{} has a genotype with {max_ploidy} alleles, \
+                    but sample {name} has {n}",
+                            record.chromosome(),
+                            record.position()
+                        ),
+                        call.head,
+                    ),
+            );
+        }
+    }
 
-    // now match on compression
-    match reader {
-        VCFReader::Uncompressed(uc) => {
-            iterate_vcf_records(*uc, header, call, &mut value_records).unwrap();
+    *record.genotypes_mut() = Genotypes::new(genotypes.keys().clone(), values);
+
+    Ok(())
+}
+
+/// Go from a parsed nuon V/BCF structure to BGZF-compressed BCF bytes.
+pub fn nuon_to_bcf(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let (header, mut records) = nuon_to_vcf_header_and_records(call, input)?;
+
+    // BCF refers to contigs and strings by their index in the header, so make
+    // sure every record can be encoded before writing anything.
+    let string_maps = StringMaps::try_from(&header).map_err(|e| {
+        LabeledError::new("Could not build BCF string maps from the header.")
+            .with_label(format!("cause of failure: {}", e), call.head)
+    })?;
+
+    for record in &mut records {
+        let chrom = record.chromosome().to_string();
+        if string_maps.contigs().get_index_of(&chrom).is_none() {
+            return Err(
+                LabeledError::new("Chromosome is missing from the header contigs.").with_label(
+                    format!(
+                        "record at {}:{} has no contig entry",
+                        chrom,
+                        record.position()
+                    ),
+                    call.head,
+                ),
+            );
+        }
+        bcf_genotypes(call, &header, record)?;
+    }
+
+    let write_error = |err: std::io::Error| {
+        LabeledError::new("Error in writing BCF").with_label(err.to_string(), call.head)
+    };
+
+    let mut writer = bcf::Writer::new(Vec::new());
+    writer.write_header(&header).map_err(write_error)?;
+    for record in records {
+        writer.write_record(&header, &record).map_err(write_error)?;
+    }
+
+    // writes the BGZF EOF marker.
+    let out_final = writer.into_inner().finish().map_err(write_error)?;
+
+    Ok(Value::binary(out_final, call.head))
+}
+
+/// Melt the output of `from vcf` or `from bcf` (or just its body) into
+/// one row per variant and sample, with the GT and every FORMAT field as
+/// columns. Fields a sample does not have are nothing.
+pub fn vcf_long(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let body = match input {
+        Value::Record { .. } => input.get_data_by_key("body").ok_or(
+            LabeledError::new("Input should have a body.")
+                .with_label("requires the output of `from vcf` or `from bcf`", call.head),
+        )?,
+        other => other.clone(),
+    };
+    let body = body.as_list()?;
+
+    // every FORMAT key in the body, in order of appearance, GT first.
+    let mut keys = vec!["GT".to_string()];
+    for row in body {
+        if let Some(Value::Record { val, .. }) = row.get_data_by_key(VCF_COLUMNS[9]) {
+            for sample in val.values() {
+                if let Value::Record { val: fields, .. } = sample {
+                    for key in fields.columns() {
+                        if !keys.contains(key) {
+                            keys.push(key.clone());
+                        }
+                    }
+                }
+            }
         }
-        VCFReader::Compressed(c) => {
-            iterate_vcf_records(*c, header, call, &mut value_records).unwrap();
+    }
+
+    let mut rows = Vec::new();
+
+    for row in body {
+        let samples = match row.get_data_by_key(VCF_COLUMNS[9]) {
+            Some(Value::Record { val, .. }) => val,
+            _ => continue,
+        };
+
+        for (name, sample) in samples.iter() {
+            let mut cols = vec!["chrom", "pos", "ref", "alt", "sample"]
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>();
+            let mut vals = vec![
+                row.get_data_by_key(VCF_COLUMNS[0])
+                    .unwrap_or(Value::nothing(call.head)),
+                row.get_data_by_key(VCF_COLUMNS[1])
+                    .unwrap_or(Value::nothing(call.head)),
+                row.get_data_by_key(VCF_COLUMNS[5])
+                    .unwrap_or(Value::nothing(call.head)),
+                row.get_data_by_key(VCF_COLUMNS[6])
+                    .unwrap_or(Value::nothing(call.head)),
+                call.head.with_string(name),
+            ];
+
+            for key in &keys {
+                cols.push(key.clone());
+                vals.push(
+                    sample
+                        .get_data_by_key(key)
+                        .unwrap_or(Value::nothing(call.head)),
+                );
+            }
+
+            rows.push(Value::record(
+                Record::from_iter(cols.into_iter().zip(vals)),
+                call.head,
+            ));
         }
     }
 
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head),
-        },
-        call.head,
-    ))
+    Ok(Value::list(rows, call.head))
 }
@@ -1,13 +1,20 @@
-use noodles::bed;
-use nu_plugin::{EvaluatedCall, LabeledError};
-use nu_protocol::{Record, Value};
+use noodles::bed::{
+    self,
+    record::{BedN, ParseError},
+};
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{LabeledError, Record, Span, Value};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::{self, BufRead};
+use std::str::FromStr;
 
-use super::SpanExt;
+use super::{Records, SpanExt};
 
-/// BED reader type
-const BED_COLUMN_NUMBER: u8 = 3;
+/// The BED widths we can read, BED10 and BED11 are not valid.
+const BED_WIDTHS: &[u8] = &[3, 4, 5, 6, 7, 8, 9, 12];
 
-/// Columns in a BAM/SAM file
+/// Columns in a BED file
 pub const BED_COLUMNS: &[&str] = &[
     // Mandatory, name of chromosome
     "chrom",
@@ -15,43 +22,507 @@ pub const BED_COLUMNS: &[&str] = &[
     "chromStart",
     // Mandatory, end position
     "chromEnd",
+    // Optional, name of the line
+    "name",
+    // Optional, score between 0 and 1000
+    "score",
+    // Optional, strand, one of +, - or .
+    "strand",
+    // Optional, start position of thick drawing
+    "thickStart",
+    // Optional, end position of thick drawing
+    "thickEnd",
+    // Optional, RGB value, e.g. 255,0,0
+    "itemRgb",
+    // Optional, number of blocks (exons)
+    "blockCount",
+    // Optional, comma separated list of block sizes
+    "blockSizes",
+    // Optional, comma separated list of block starts, relative to chromStart
+    "blockStarts",
 ];
 
-pub fn from_bed_inner(call: &EvaluatedCall, input: Value) -> Result<Vec<Value>, LabeledError> {
-    let bytes = match input.as_binary() {
-        Ok(b) => b,
-        Err(e) => {
-            return Err(LabeledError {
-                label: "Value conversion to binary failed.".into(),
-                msg: format!("cause of failure: {}", e),
-                span: Some(call.head),
+/// Columns after the standard ones are kept as a list here.
+const BED_EXTRA_COLUMN: &str = "extra";
+
+/// Whether a line is a UCSC `track` or `browser` line.
+fn is_track_line(line: &str) -> bool {
+    ["track", "browser"].iter().any(|key| {
+        line.strip_prefix(key)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t']))
+    })
+}
+
+/// The records of a BED with their line numbers, skipping blank lines,
+/// comments, and `track` and `browser` lines.
+fn bed_lines<R: BufRead>(
+    head: Span,
+    reader: R,
+) -> impl Iterator<Item = Result<(usize, String), LabeledError>> {
+    reader
+        .lines()
+        .enumerate()
+        .map(move |(i, line)| {
+            line.map(|line| (i + 1, line)).map_err(|e| {
+                LabeledError::new(match e.kind() {
+                    io::ErrorKind::InvalidData => "BED input is not UTF-8.".into(),
+                    _ => format!("Could not read line {} of the BED input.", i + 1),
+                })
+                .with_label(format!("cause of failure: {}", e), head)
             })
-        }
-    };
+        })
+        .filter(|line| {
+            !matches!(line, Ok((_, line)) if line.is_empty() || line.starts_with('#') || is_track_line(line))
+        })
+}
+
+/// Work out the BED width from the first record, returning the number
+/// of standard columns and whether there are any trailing columns.
+fn detect_bed_width(first: Option<&str>) -> (u8, bool) {
+    let fields = first.map(|line| line.split('\t').count()).unwrap_or(3);
+
+    let width = BED_WIDTHS
+        .iter()
+        .rev()
+        .find(|w| **w as usize <= fields)
+        .copied()
+        .unwrap_or(3);
+
+    (width, fields > width as usize)
+}
+
+/// BED3: chrom, chromStart, chromEnd.
+fn add_bed3_fields<const N: u8>(call: &EvaluatedCall, r: &bed::Record<N>, row: &mut Vec<Value>)
+where
+    bed::Record<N>: BedN<3>,
+{
+    row.push(call.head.with_string(r.reference_sequence_name()));
+    let start: usize = r.start_position().into();
+    row.push(Value::int(start as i64, call.head));
+    let end: usize = r.end_position().into();
+    row.push(Value::int(end as i64, call.head));
+}
 
-    let mut reader = bed::Reader::new(bytes);
+/// BED4: adds name.
+fn add_bed4_fields<const N: u8>(call: &EvaluatedCall, r: &bed::Record<N>, row: &mut Vec<Value>)
+where
+    bed::Record<N>: BedN<3> + BedN<4>,
+{
+    add_bed3_fields(call, r, row);
+    row.push(
+        r.name()
+            .map(|n| call.head.with_string(n))
+            .unwrap_or(Value::nothing(call.head)),
+    );
+}
+
+/// BED5: adds score, where zero is missing.
+fn add_bed5_fields<const N: u8>(call: &EvaluatedCall, r: &bed::Record<N>, row: &mut Vec<Value>)
+where
+    bed::Record<N>: BedN<3> + BedN<4> + BedN<5>,
+{
+    add_bed4_fields(call, r, row);
+    let score = r.score().map(|s| u16::from(s) as i64).unwrap_or(0);
+    row.push(Value::int(score, call.head));
+}
+
+/// BED6: adds strand.
+fn add_bed6_fields<const N: u8>(call: &EvaluatedCall, r: &bed::Record<N>, row: &mut Vec<Value>)
+where
+    bed::Record<N>: BedN<3> + BedN<4> + BedN<5> + BedN<6>,
+{
+    add_bed5_fields(call, r, row);
+    row.push(call.head.with_string_or(r.strand(), "."));
+}
+
+/// BED7: adds thickStart.
+fn add_bed7_fields<const N: u8>(call: &EvaluatedCall, r: &bed::Record<N>, row: &mut Vec<Value>)
+where
+    bed::Record<N>: BedN<3> + BedN<4> + BedN<5> + BedN<6> + BedN<7>,
+{
+    add_bed6_fields(call, r, row);
+    let thick_start: usize = r.thick_start().into();
+    row.push(Value::int(thick_start as i64, call.head));
+}
+
+/// BED8: adds thickEnd.
+fn add_bed8_fields<const N: u8>(call: &EvaluatedCall, r: &bed::Record<N>, row: &mut Vec<Value>)
+where
+    bed::Record<N>: BedN<3> + BedN<4> + BedN<5> + BedN<6> + BedN<7> + BedN<8>,
+{
+    add_bed7_fields(call, r, row);
+    let thick_end: usize = r.thick_end().into();
+    row.push(Value::int(thick_end as i64, call.head));
+}
 
-    let mut records = Vec::new();
+/// BED9: adds itemRgb, where zero is missing.
+fn add_bed9_fields<const N: u8>(call: &EvaluatedCall, r: &bed::Record<N>, row: &mut Vec<Value>)
+where
+    bed::Record<N>: BedN<3> + BedN<4> + BedN<5> + BedN<6> + BedN<7> + BedN<8> + BedN<9>,
+{
+    add_bed8_fields(call, r, row);
+    row.push(call.head.with_string_or(r.color(), "0"));
+}
+
+/// BED12: adds blockCount, blockSizes and blockStarts.
+fn add_bed12_fields(call: &EvaluatedCall, r: &bed::Record<12>, row: &mut Vec<Value>) {
+    add_bed9_fields(call, r, row);
+    let blocks = r.blocks();
+    row.push(Value::int(blocks.len() as i64, call.head));
+    row.push(Value::list(
+        blocks
+            .iter()
+            .map(|(_, size)| Value::int(*size as i64, call.head))
+            .collect(),
+        call.head,
+    ));
+    row.push(Value::list(
+        blocks
+            .iter()
+            .map(|(start, _)| Value::int(*start as i64, call.head))
+            .collect(),
+        call.head,
+    ));
+}
 
-    for result in reader.records::<BED_COLUMN_NUMBER>() {
-        let record = result.map_err(|e| LabeledError {
-            label: "Failed reading a record in the BED file".into(),
-            msg: format!("{e}"),
-            span: Some(call.head),
+/// Iterate lazily over the records of a BED with `N` standard columns.
+fn iterate_bed_records<const N: u8>(
+    lines: impl Iterator<Item = Result<(usize, String), LabeledError>> + Send + 'static,
+    call: EvaluatedCall,
+    add_fields: fn(&EvaluatedCall, &bed::Record<N>, &mut Vec<Value>),
+    extra: bool,
+) -> Records
+where
+    bed::Record<N>: BedN<3> + FromStr<Err = ParseError>,
+{
+    let mut cols: Vec<String> = BED_COLUMNS[..N as usize]
+        .iter()
+        .map(|e| e.to_string())
+        .collect();
+    if extra {
+        cols.push(BED_EXTRA_COLUMN.into());
+    }
+
+    Box::new(lines.map(move |line| {
+        let (line_number, line) = line?;
+        // UCSC writes blockSizes and blockStarts with a trailing comma.
+        let line: Cow<str> = if N == 12 {
+            let fields: Vec<&str> = line
+                .split('\t')
+                .enumerate()
+                .map(|(i, f)| match i {
+                    10 | 11 => f.trim_end_matches(','),
+                    _ => f,
+                })
+                .collect();
+            Cow::Owned(fields.join("\t"))
+        } else {
+            Cow::Borrowed(line.as_str())
+        };
+
+        let record: bed::Record<N> = line.parse().map_err(|e| {
+            LabeledError::new(format!(
+                "Failed reading the record on line {line_number} of the BED{N} file"
+            ))
+            .with_label(
+                format!("{e}, use --columns to parse fewer standard columns"),
+                call.head,
+            )
         })?;
 
         let mut row = Vec::new();
+        add_fields(&call, &record, &mut row);
+
+        if extra {
+            row.push(Value::list(
+                record
+                    .optional_fields()
+                    .iter()
+                    .map(|e| call.head.with_string(e))
+                    .collect(),
+                call.head,
+            ));
+        }
+
+        let record_inner = Record::from_iter(cols.iter().cloned().zip(row));
 
-        row.push(call.head.with_string(record.reference_sequence_name()));
-        let start: usize = record.start_position().into();
-        row.push(Value::int(start as i64, call.head));
-        let end: usize = record.end_position().into();
-        row.push(Value::int(end as i64, call.head));
+        Ok(Value::record(record_inner, call.head))
+    }))
+}
+
+/// Parse a BED file into a nushell structure, lazily. The width is
+/// detected from the first record, which is peeked at before the records
+/// are parsed.
+pub fn from_bed_inner<R: BufRead + Send + 'static>(
+    call: &EvaluatedCall,
+    reader: R,
+) -> Result<Records, LabeledError> {
+    let mut lines = bed_lines(call.head, reader).peekable();
+
+    // an error reading the first record is returned when it is parsed.
+    let first = lines.peek().and_then(|line| line.as_ref().ok());
+    let (detected_width, detected_extra) = detect_bed_width(first.map(|(_, line)| line.as_str()));
 
-        let record_inner = Record::from_iter(BED_COLUMNS.iter().map(|e| e.to_string()).zip(row));
+    // the user can ask for fewer standard columns, the rest are kept as extra.
+    let (width, extra) = match call.get_flag::<i64>("columns")? {
+        Some(n) if (3..=12).contains(&n) && BED_WIDTHS.contains(&(n as u8)) => {
+            (n as u8, detected_extra || (n as u8) < detected_width)
+        }
+        Some(n) => {
+            return Err(
+                LabeledError::new("Invalid number of BED columns.").with_label(
+                    format!("--columns must be one of 3, 4, 5, 6, 7, 8, 9 or 12, got {n}"),
+                    call.head,
+                ),
+            )
+        }
+        None => (detected_width, detected_extra),
+    };
+
+    let call = call.clone();
+
+    Ok(match width {
+        3 => iterate_bed_records::<3>(lines, call, add_bed3_fields, extra),
+        4 => iterate_bed_records::<4>(lines, call, add_bed4_fields, extra),
+        5 => iterate_bed_records::<5>(lines, call, add_bed5_fields, extra),
+        6 => iterate_bed_records::<6>(lines, call, add_bed6_fields, extra),
+        7 => iterate_bed_records::<7>(lines, call, add_bed7_fields, extra),
+        8 => iterate_bed_records::<8>(lines, call, add_bed8_fields, extra),
+        9 => iterate_bed_records::<9>(lines, call, add_bed9_fields, extra),
+        _ => iterate_bed_records::<12>(lines, call, add_bed12_fields, extra),
+    })
+}
+
+/// Format a BED list column, which may be either a nushell list or
+/// an already comma separated string.
+fn format_bed_list(value: &Value) -> Result<(usize, String), LabeledError> {
+    match value {
+        Value::List { vals, .. } => {
+            let items = vals
+                .iter()
+                .map(|v| v.as_int().map(|i| i.to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((items.len(), items.join(",")))
+        }
+        other => {
+            let s = other.coerce_string()?;
+            let s = s.trim_end_matches(',');
+            let len = if s.is_empty() {
+                0
+            } else {
+                s.split(',').count()
+            };
+            Ok((len, s.to_string()))
+        }
+    }
+}
+
+/// Format a single BED row, given the number of standard columns to write.
+fn format_bed_row(
+    call: &EvaluatedCall,
+    row: &Value,
+    index: usize,
+    width: usize,
+) -> Result<String, LabeledError> {
+    let error = |msg: String| {
+        LabeledError::new(format!("Invalid BED row at index {index}.")).with_label(msg, row.span())
+    };
+    // presence of all the columns has been checked by the caller.
+    let get = |col: &str| {
+        row.get_data_by_key(col)
+            .unwrap_or(Value::nothing(call.head))
+    };
+
+    let mut fields = Vec::with_capacity(width);
+
+    fields.push(get(BED_COLUMNS[0]).coerce_string()?);
+
+    // positions are 1-based, as `from bed` produces them.
+    let start = get(BED_COLUMNS[1]).as_int()?;
+    let end = get(BED_COLUMNS[2]).as_int()?;
+    if start < 1 {
+        return Err(error(format!(
+            "chromStart must be at least 1 (positions are 1-based as in `from bed`), got {start}"
+        )));
+    }
+    if end < start - 1 {
+        return Err(error(format!(
+            "chromEnd ({end}) is before chromStart ({start})"
+        )));
+    }
+    fields.push((start - 1).to_string());
+    fields.push(end.to_string());
+
+    if width >= 4 {
+        // `from bed` gives nothing for a missing name.
+        let name = get(BED_COLUMNS[3]);
+        if name.is_nothing() {
+            fields.push(".".into());
+        } else {
+            fields.push(name.coerce_string()?);
+        }
+    }
+
+    if width >= 5 {
+        let score = get(BED_COLUMNS[4]).as_int()?;
+        if !(0..=1000).contains(&score) {
+            return Err(error(format!(
+                "score must be between 0 and 1000, got {score}"
+            )));
+        }
+        fields.push(score.to_string());
+    }
+
+    if width >= 6 {
+        let strand = get(BED_COLUMNS[5]).coerce_string()?;
+        if !matches!(strand.as_str(), "+" | "-" | ".") {
+            return Err(error(format!(
+                "strand must be one of +, - or ., got {strand:?}"
+            )));
+        }
+        fields.push(strand);
+    }
+
+    if width >= 7 {
+        let thick_start = get(BED_COLUMNS[6]).as_int()?;
+        if thick_start < 1 {
+            return Err(error(format!(
+                "thickStart must be at least 1 (positions are 1-based as in `from bed`), got {thick_start}"
+            )));
+        }
+        fields.push((thick_start - 1).to_string());
+    }
+
+    if width >= 8 {
+        fields.push(get(BED_COLUMNS[7]).as_int()?.to_string());
+    }
+
+    if width >= 9 {
+        let item_rgb = match get(BED_COLUMNS[8]) {
+            Value::List { vals, .. } => vals
+                .iter()
+                .map(|v| v.as_int().map(|i| i.to_string()))
+                .collect::<Result<Vec<_>, _>>()?
+                .join(","),
+            other => other.coerce_string()?,
+        };
+        fields.push(item_rgb);
+    }
+
+    if width == 12 {
+        let block_count = get(BED_COLUMNS[9]).as_int()?;
+        let (sizes_len, block_sizes) = format_bed_list(&get(BED_COLUMNS[10]))?;
+        let (starts_len, block_starts) = format_bed_list(&get(BED_COLUMNS[11]))?;
+
+        if sizes_len as i64 != block_count || starts_len as i64 != block_count {
+            return Err(error(format!(
+                "blockCount is {block_count}, but there are {sizes_len} blockSizes and {starts_len} blockStarts"
+            )));
+        }
+
+        fields.push(block_count.to_string());
+        fields.push(block_sizes);
+        fields.push(block_starts);
+    }
+
+    // trailing non-standard columns, as produced by `from bed`.
+    match get(BED_EXTRA_COLUMN) {
+        Value::List { vals, .. } => {
+            for val in vals {
+                fields.push(val.coerce_string()?);
+            }
+        }
+        Value::Nothing { .. } => (),
+        other => fields.push(other.coerce_string()?),
+    }
+
+    Ok(fields.join("\t"))
+}
+
+/// Go from a parsed nuon BED table to a BED string.
+///
+/// The width of the BED (BED3 to BED12) is inferred from the standard
+/// columns present in the first row, and every other row must match it.
+/// An `extra` column is written after the standard ones.
+pub fn nuon_to_bed(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let list = input.as_list()?;
+
+    let standard_columns = |row: &Value| -> Result<HashSet<String>, LabeledError> {
+        Ok(row
+            .as_record()?
+            .columns()
+            .filter(|c| BED_COLUMNS.contains(&c.as_str()))
+            .cloned()
+            .collect())
+    };
+
+    let first = match list.first() {
+        Some(f) => f,
+        None => return Ok(Value::string("", call.head)),
+    };
+    let columns = standard_columns(first)?;
+
+    // BEDn is always the first n columns.
+    let width = BED_COLUMNS
+        .iter()
+        .take_while(|c| columns.contains(**c))
+        .count();
+
+    if width < 3 {
+        return Err(
+            LabeledError::new("Missing mandatory BED columns.").with_label(
+                format!(
+                    "a BED requires at least the {} columns",
+                    BED_COLUMNS[..3].join(", ")
+                ),
+                first.span(),
+            ),
+        );
+    }
+
+    if let Some(col) = BED_COLUMNS[width..].iter().find(|c| columns.contains(**c)) {
+        return Err(
+            LabeledError::new("BED columns are not contiguous.").with_label(
+                format!("found column {col} without column {}", BED_COLUMNS[width]),
+                first.span(),
+            ),
+        );
+    }
+
+    if width == 10 || width == 11 {
+        return Err(
+            LabeledError::new("Incomplete BED block columns.").with_label(
+                "blockCount, blockSizes and blockStarts must be given together",
+                first.span(),
+            ),
+        );
+    }
+
+    let mut out = String::new();
+
+    for (index, row) in list.iter().enumerate() {
+        let row_columns = standard_columns(row)?;
+        if row_columns != columns {
+            let missing: Vec<_> = BED_COLUMNS
+                .iter()
+                .filter(|c| columns.contains(**c) && !row_columns.contains(**c))
+                .copied()
+                .collect();
+            let extra: Vec<_> = BED_COLUMNS
+                .iter()
+                .filter(|c| !columns.contains(**c) && row_columns.contains(**c))
+                .copied()
+                .collect();
+            return Err(LabeledError::new(format!("Inconsistent BED row at index {index}.")).with_label(format!(
+                    "row does not match the BED{width} columns of the first row (missing: [{}], extra: [{}])",
+                    missing.join(", "),
+                    extra.join(", ")
+                ), row.span()));
+        }
 
-        records.push(Value::record(record_inner, call.head))
+        out.push_str(&format_bed_row(call, row, index, width)?);
+        out.push('\n');
     }
 
-    Ok(records)
+    Ok(Value::string(out, call.head))
 }
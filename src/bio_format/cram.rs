@@ -1,77 +1,370 @@
 /// The CRAM format
+use md5::{Digest, Md5};
 use noodles::cram;
+use noodles::fasta;
 use noodles::sam;
-use nu_plugin::{EvaluatedCall, LabeledError};
-use nu_protocol::{record, Record, Value};
-
-use crate::bio_format::bam::{create_record_values, parse_header, BAM_COLUMNS};
-// TODO: also allow the reference to be passed, so we can view the alignment sequences?
-
-/// Parse a CRAM file into a nushell structure.
-pub fn from_cram_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val,
-        other => {
-            return Err(LabeledError {
-                label: "Input should be binary.".into(),
-                msg: format!("requires binary input, got {}", other.get_type()),
-                span: Some(call.head),
-            })
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{LabeledError, Spanned, Value};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread;
+
+use crate::bio_format::bam::{create_record, nuon_to_header_and_records, parse_header};
+use crate::bio_format::Records;
+
+/// The MD5 of a reference sequence, as in the `M5` tag of `@SQ` header
+/// lines: upper case, and without whitespace.
+fn normalized_md5(sequence: &[u8]) -> String {
+    let mut hasher = Md5::new();
+    for &base in sequence {
+        if base.is_ascii_graphic() {
+            hasher.update([base.to_ascii_uppercase()]);
         }
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Build a reference sequence repository from a FASTA with a `.fai` index,
+/// checking every `@SQ` with an MD5 in the header is in the reference, and
+/// matches it.
+pub fn reference_repository(
+    path: &Spanned<String>,
+    header: &sam::Header,
+) -> Result<fasta::Repository, LabeledError> {
+    let fasta_path = Path::new(&path.item);
+
+    let reader = fasta::indexed_reader::Builder::default()
+        .build_from_path(fasta_path)
+        .map_err(|e| {
+            LabeledError::new("Could not open the reference.").with_label(
+                format!(
+                    "{} needs a .fai index (see `bio index fasta`), cause of failure: {}",
+                    fasta_path.display(),
+                    e
+                ),
+                path.span,
+            )
+        })?;
+
+    let repository =
+        fasta::Repository::new(fasta::repository::adapters::IndexedReader::new(reader));
+
+    for (name, reference_sequence) in header.reference_sequences() {
+        let Some(expected) = reference_sequence.md5_checksum() else {
+            continue;
+        };
+
+        let sequence = match repository.get(name.as_str()) {
+            Some(Ok(sequence)) => sequence,
+            _ => {
+                return Err(LabeledError::new("Reference sequence missing.").with_label(
+                    format!(
+                        "@SQ {name} is not in the reference {}",
+                        fasta_path.display()
+                    ),
+                    path.span,
+                ))
+            }
+        };
+
+        let actual = normalized_md5(sequence.as_ref());
+        if actual != expected.to_string() {
+            return Err(LabeledError::new("Reference MD5 mismatch.").with_label(format!(
+                    "@SQ {name} has M5 {expected}, but the sequence in {} has MD5 {actual}, is this the right reference?",
+                    fasta_path.display()
+                ), path.span));
+        }
+    }
+
+    Ok(repository)
+}
+
+/// Check the next container fits in what is left of the input, before it is
+/// read. A CRAM must end with an EOF container, so running out of input is
+/// an error, and a truncated container would otherwise have its full length
+/// allocated before the read fails.
+fn check_container_length<R: Read + Seek>(
+    call: &EvaluatedCall,
+    reader: &mut cram::Reader<R>,
+    length: u64,
+    container_index: usize,
+) -> Result<(), LabeledError> {
+    let truncated = |msg: String| {
+        LabeledError::new(format!(
+            "CRAM file is truncated at container {container_index}."
+        ))
+        .with_label(msg, call.head)
+    };
+    let io_error = |e: io::Error| {
+        LabeledError::new(format!("Could not read CRAM container {container_index}."))
+            .with_label(format!("cause of failure: {}", e), call.head)
     };
 
-    let mut reader = cram::Reader::new(stream.as_slice());
+    let position = reader.position().map_err(io_error)?;
+    let remaining = length.saturating_sub(position);
+
+    match remaining {
+        0 => return Err(truncated("the input ended without an EOF container".into())),
+        1..=3 => return Err(truncated("the container header is incomplete".into())),
+        _ => (),
+    }
+
+    // peek at the container length, the first field of its header.
+    let mut buf = [0; 4];
+    reader.get_mut().read_exact(&mut buf).map_err(io_error)?;
+    reader.seek(SeekFrom::Start(position)).map_err(io_error)?;
+    let container_length = i32::from_le_bytes(buf);
+
+    if u64::try_from(container_length).map_or(true, |l| l > remaining) {
+        return Err(truncated(format!(
+            "the container is {container_length} bytes long, but only {remaining} bytes are left"
+        )));
+    }
+
+    Ok(())
+}
+
+/// How many records are read ahead of nushell.
+const READ_AHEAD: usize = 1024;
+
+/// Read the records of the data containers, sending them as rows of the
+/// body until nushell stops asking for them.
+fn send_records<R: Read + Seek>(
+    call: &EvaluatedCall,
+    reader: &mut cram::Reader<R>,
+    length: u64,
+    header: &sam::Header,
+    repository: Option<&fasta::Repository>,
+    sender: &SyncSender<Result<Value, LabeledError>>,
+) -> Result<(), LabeledError> {
+    for container_index in 0.. {
+        check_container_length(call, reader, length, container_index)?;
+
+        let container = match reader.read_data_container() {
+            Ok(Some(container)) => container,
+            // the EOF container.
+            Ok(None) => break,
+            Err(e) => {
+                return Err(LabeledError::new(format!(
+                    "Could not read CRAM container {container_index}."
+                ))
+                .with_label(format!("cause of failure: {}", e), call.head))
+            }
+        };
+        let compression_header = container.compression_header();
+
+        for (slice_index, slice) in container.slices().iter().enumerate() {
+            let location = format!("container {container_index}, slice {slice_index}");
 
+            let mut records = slice.records(compression_header).map_err(|e| {
+                LabeledError::new(format!("Could not read the records of CRAM {location}."))
+                    .with_label(format!("cause of failure: {}", e), call.head)
+            })?;
+
+            if let Some(repository) = repository {
+                slice
+                    .resolve_records(repository, header, compression_header, &mut records)
+                    .map_err(|e| {
+                        LabeledError::new(format!(
+                            "Could not restore the reads of CRAM {location} from the reference."
+                        ))
+                        .with_label(format!("cause of failure: {}", e), call.head)
+                    })?;
+            }
+
+            for (record_index, r) in records.into_iter().enumerate() {
+                let r = r.try_into_alignment_record(header).map_err(|e| {
+                    LabeledError::new(format!("Invalid record {record_index} in CRAM {location}."))
+                        .with_label(format!("cause of failure: {}", e), call.head)
+                })?;
+                // nushell has stopped reading.
+                if sender.send(Ok(create_record(call, header, r)?)).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the file definition and header of a CRAM.
+fn read_cram_header<R: Read>(
+    call: &EvaluatedCall,
+    reader: &mut cram::Reader<R>,
+) -> Result<sam::Header, LabeledError> {
     match reader.read_file_definition() {
         Ok(_) => (),
         Err(e) => {
-            return Err(LabeledError {
-                label: "Could not read CRAM file definition.".into(),
-                msg: format!("cause of failure: {}", e),
-                span: Some(call.head),
-            })
+            return Err(LabeledError::new("Could not read CRAM file definition.")
+                .with_label(format!("cause of failure: {}", e), call.head))
         }
     };
 
-    let header: sam::Header = match reader.read_file_header() {
-        Ok(s) => s,
-        Err(e) => {
-            return Err(LabeledError {
-                label: "CRAM file header reading failed.".into(),
-                msg: format!("cause of failure: {}", e),
-                span: Some(call.head),
-            })
+    match reader.read_file_header() {
+        Ok(s) => Ok(s),
+        Err(e) => Err(LabeledError::new("CRAM file header reading failed.")
+            .with_label(format!("cause of failure: {}", e), call.head)),
+    }
+}
+
+/// Parse a CRAM file into its header and records, from a reader over
+/// `length` bytes of input.
+///
+/// The CRAM reader, and the reference sequence repository, cannot be sent
+/// between threads, so the file is read on a thread of their own, which
+/// sends back the header and then the records.
+pub fn from_cram_inner<R: Read + Seek + Send + 'static>(
+    call: &EvaluatedCall,
+    stream: R,
+    length: u64,
+) -> Result<(Value, Records), LabeledError> {
+    // without a reference, the bases of reference-compressed reads cannot be
+    // restored, so they are left empty.
+    let reference = call.get_flag::<Spanned<String>>("reference")?;
+
+    let (header_sender, header_receiver) = mpsc::sync_channel(1);
+    let (sender, receiver) = mpsc::sync_channel(READ_AHEAD);
+    let thread_call = call.clone();
+
+    thread::spawn(move || {
+        let call = thread_call;
+        let mut reader = cram::Reader::new(stream);
+
+        let header = read_cram_header(&call, &mut reader).and_then(|header| {
+            let repository = reference
+                .map(|path| reference_repository(&path, &header))
+                .transpose()?;
+            Ok((header, repository))
+        });
+        let (header, repository) = match header {
+            Ok(header) => header,
+            Err(e) => {
+                let _ = header_sender.send(Err(e));
+                return;
+            }
+        };
+        if header_sender
+            .send(Ok(parse_header(&call, &header)))
+            .is_err()
+        {
+            return;
         }
-    };
 
-    let header_nuon = parse_header(call, &header);
+        let sent = send_records(
+            &call,
+            &mut reader,
+            length,
+            &header,
+            repository.as_ref(),
+            &sender,
+        );
+        if let Err(e) = sent {
+            let _ = sender.send(Err(e));
+        }
+    });
+
+    let header_nuon = header_receiver.recv().map_err(|e| {
+        LabeledError::new("Could not read the CRAM file.")
+            .with_label(format!("cause of failure: {}", e), call.head)
+    })??;
+
+    Ok((header_nuon, Box::new(receiver.into_iter())))
+}
+
+/// Write the structured output of `from sam`, `from bam` or `from cram` to
+/// CRAM 3.0 bytes, compressing the read sequences against the reference.
+/// Mapped records need quality scores.
+pub fn nuon_to_cram(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let (header, records) = nuon_to_header_and_records(call, input)?;
+
+    let reference: Spanned<String> =
+        call.get_flag("reference")?
+            .ok_or(LabeledError::new("No reference given.").with_label(
+                "CRAM is compressed against a reference, give one with --reference",
+                call.head,
+            ))?;
+    let repository = reference_repository(&reference, &header)?;
 
-    let mut value_records = Vec::new();
+    let mut writer = cram::writer::Builder::default()
+        .set_reference_sequence_repository(repository)
+        .build_with_writer(Vec::new());
 
-    while let Some(container) = reader.read_data_container().unwrap() {
-        for slice in container.slices() {
-            // FIXME: if these unwraps in this section are converted to match LabeledErrors as
-            // above, some of my test crams hang indefinitely in parsing(?)
-            let records = slice.records(container.compression_header()).unwrap();
+    writer
+        .write_file_definition()
+        .and_then(|_| writer.write_file_header(&header))
+        .map_err(|err| {
+            LabeledError::new("Error in writing CRAM header").with_label(err.to_string(), call.head)
+        })?;
 
-            for r in records {
-                let r = r.try_into_alignment_record(&header).unwrap();
-                let vec_vals = create_record_values(call, r);
+    for record in records {
+        if !record.flags().is_unmapped() {
+            let read_length = record.cigar().read_length();
+            let read_name = record
+                .read_name()
+                .map(|n| n.to_string())
+                .unwrap_or_default();
 
-                let records_inner =
-                    Record::from_iter(BAM_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+            if record.sequence().len() != read_length {
+                return Err(LabeledError::new(format!("Error in writing record ({read_name}) to CRAM")).with_label(format!(
+                        "the sequence is {} bases long, but the CIGAR is {read_length}, mapped records need their whole sequence",
+                        record.sequence().len()
+                    ), call.head));
+            }
 
-                value_records.push(Value::record(records_inner, call.head))
+            // noodles cannot write a mapped record with missing quality scores,
+            // and making them up would be worse than not writing the CRAM.
+            if record.quality_scores().is_empty() {
+                return Err(LabeledError::new(format!(
+                    "Error in writing record ({read_name}) to CRAM"
+                ))
+                .with_label(
+                    "the record has no quality scores, which mapped records need in CRAM",
+                    call.head,
+                ));
+            }
+            if record.quality_scores().len() != read_length {
+                return Err(LabeledError::new(format!(
+                    "Error in writing record ({read_name}) to CRAM"
+                ))
+                .with_label(
+                    format!(
+                        "there are {} quality scores, but {read_length} bases",
+                        record.quality_scores().len()
+                    ),
+                    call.head,
+                ));
             }
         }
+
+        let record_error = |err: std::io::Error| {
+            LabeledError::new(format!(
+                "Error in writing record ({}) to CRAM",
+                record
+                    .read_name()
+                    .map(|n| n.to_string())
+                    .unwrap_or_default()
+            ))
+            .with_label(err.to_string(), call.head)
+        };
+
+        let cram_record =
+            cram::Record::try_from_alignment_record(&header, &record).map_err(record_error)?;
+        writer
+            .write_record(&header, cram_record)
+            .map_err(record_error)?;
     }
 
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head)
-        },
-        call.head,
-    ))
+    // writes the last data container, and the EOF container.
+    writer.try_finish(&header).map_err(|err| {
+        LabeledError::new("Error in finishing the CRAM stream")
+            .with_label(err.to_string(), call.head)
+    })?;
+
+    Ok(Value::binary(writer.get_ref().clone(), call.head))
 }
@@ -1,5 +1,8 @@
-use std::io::{BufRead, BufReader};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
+use noodles::core::{Position, Region};
 use noodles::fasta::{
     record::{Definition as FastaDefinition, Record as FastaRecord, Sequence},
     Writer as FastaWriter,
@@ -9,77 +12,86 @@ use noodles::fastq::{
     Writer as FastqWriter,
 };
 use noodles::{bgzf, fasta, fastq};
-use nu_plugin::{EvaluatedCall, LabeledError};
-use nu_protocol::Value;
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{LabeledError, Spanned, Value};
 
-use crate::bio_format::{Compression, SpanExt};
+use crate::bio_format::query::find_index;
+use crate::bio_format::{Compression, Records, SpanExt};
 
 /// Compression status of a fastq reader.
-enum FastqReader<'a> {
-    Uncompressed(Box<fastq::Reader<&'a [u8]>>),
-    Compressed(Box<fastq::Reader<BufReader<bgzf::Reader<&'a [u8]>>>>),
+enum FastqReader<R> {
+    Uncompressed(Box<fastq::Reader<R>>),
+    Compressed(Box<fastq::Reader<BufReader<bgzf::Reader<R>>>>),
 }
 
 /// Compression status of a fasta reader.
-enum FastaReader<'a> {
-    Uncompressed(Box<fasta::Reader<&'a [u8]>>),
-    Compressed(fasta::Reader<Box<bgzf::Reader<&'a [u8]>>>),
+enum FastaReader<R> {
+    Uncompressed(Box<fasta::Reader<R>>),
+    Compressed(fasta::Reader<Box<bgzf::Reader<R>>>),
 }
 
-/// Iterate over the records of a reader that implements [`BufRead`].
-fn iterate_fastq_records<R: BufRead>(
+/// Iterate lazily over the records of a reader that implements [`BufRead`].
+fn iterate_fastq_records<R: BufRead + Send + 'static>(
     mut reader: fastq::Reader<R>,
-    call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
+    call: EvaluatedCall,
     description: bool,
     quality_scores: bool,
     cols: Vec<String>,
-) -> Result<(), LabeledError> {
-    // iterate over the records.
-    for record in reader.records() {
-        let r = record.map_err(|e| LabeledError {
-            label: "Record reading failed.".into(),
-            msg: format!("cause of failure: {}", e),
-            span: Some(call.head),
-        })?;
-
-        let mut vec_vals = Vec::new();
-        vec_vals.push(call.head.with_string_from_utf8(r.name()));
+) -> Records {
+    // each record is read as the stream is pulled.
+    Box::new(std::iter::from_fn(move || {
+        let record = reader.records().next()?;
+        Some(fastq_record_to_nuon(
+            &call,
+            record,
+            description,
+            quality_scores,
+            &cols,
+        ))
+    }))
+}
 
-        if description {
-            vec_vals.push(call.head.with_string_from_utf8(r.description()));
-        }
+/// Make a row of the table from a fastq record.
+fn fastq_record_to_nuon(
+    call: &EvaluatedCall,
+    record: io::Result<FastqRecord>,
+    description: bool,
+    quality_scores: bool,
+    cols: &[String],
+) -> Result<Value, LabeledError> {
+    let r = record.map_err(|e| {
+        LabeledError::new("Record reading failed.")
+            .with_label(format!("cause of failure: {}", e), call.head)
+    })?;
 
-        if quality_scores {
-            vec_vals.push(call.head.with_string_from_utf8(r.quality_scores()));
-        }
+    let mut vec_vals = Vec::new();
+    vec_vals.push(call.head.with_string_from_utf8(r.name()));
 
-        vec_vals.push(call.head.with_string_from_utf8(r.sequence()));
+    if description {
+        vec_vals.push(call.head.with_string_from_utf8(r.description()));
+    }
 
-        let mut tmp_record = nu_protocol::Record::new();
-        for (col, val) in cols.clone().iter().zip(vec_vals) {
-            tmp_record.push(col, val);
-        }
-        value_records.push(Value::record(tmp_record, call.head))
+    if quality_scores {
+        vec_vals.push(call.head.with_string_from_utf8(r.quality_scores()));
     }
 
-    Ok(())
+    vec_vals.push(call.head.with_string_from_utf8(r.sequence()));
+
+    let mut tmp_record = nu_protocol::Record::new();
+    for (col, val) in cols.iter().zip(vec_vals) {
+        tmp_record.push(col, val);
+    }
+    Ok(Value::record(tmp_record, call.head))
 }
 
-pub fn from_fastq_inner(
+pub fn from_fastq_inner<R: BufRead + Send + 'static>(
     call: &EvaluatedCall,
-    input: &Value,
+    bytes: R,
     gz: Compression,
-) -> Result<Vec<Value>, LabeledError> {
+) -> Result<Records, LabeledError> {
     // parse description flag.
-    let description = call.has_flag("description");
-    let quality_scores = call.has_flag("quality-scores");
-
-    let bytes = input.as_binary().map_err(|e| LabeledError {
-        label: "Value conversion to binary failed.".into(),
-        msg: format!("cause of failure: {}", e),
-        span: Some(call.head),
-    })?;
+    let description = call.has_flag("description")?;
+    let quality_scores = call.has_flag("quality-scores")?;
 
     let reader = match gz {
         Compression::Uncompressed => FastqReader::Uncompressed(Box::new(fastq::Reader::new(bytes))),
@@ -109,74 +121,69 @@ pub fn from_fastq_inner(
         ],
     };
 
-    let mut value_records = Vec::new();
-
-    match reader {
-        FastqReader::Uncompressed(u) => iterate_fastq_records(
-            *u,
-            call,
-            &mut value_records,
-            description,
-            quality_scores,
-            cols,
-        )?,
-        FastqReader::Compressed(c) => iterate_fastq_records(
-            *c,
-            call,
-            &mut value_records,
-            description,
-            quality_scores,
-            cols,
-        )?,
-    };
+    let call = call.clone();
 
-    Ok(value_records)
+    Ok(match reader {
+        FastqReader::Uncompressed(u) => {
+            iterate_fastq_records(*u, call, description, quality_scores, cols)
+        }
+        FastqReader::Compressed(c) => {
+            iterate_fastq_records(*c, call, description, quality_scores, cols)
+        }
+    })
 }
 
-fn iterate_fasta_records<R: BufRead>(
+/// Iterate lazily over the records of a fasta reader.
+fn iterate_fasta_records<R: BufRead + Send + 'static>(
     mut reader: fasta::Reader<R>,
-    call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
+    call: EvaluatedCall,
     description: bool,
     cols: Vec<String>,
-) -> Result<(), LabeledError> {
-    // iterate over the records
-    for record in reader.records() {
-        let r = record.map_err(|e| LabeledError {
-            label: "Record reading failed.".into(),
-            msg: format!("cause of failure: {}", e),
-            span: Some(call.head),
-        })?;
+) -> Records {
+    // each record is read as the stream is pulled.
+    Box::new(std::iter::from_fn(move || {
+        let record = reader.records().next()?;
+        Some(fasta_record_to_nuon(&call, record, description, &cols))
+    }))
+}
+
+/// Make a row of the table from a fasta record.
+fn fasta_record_to_nuon(
+    call: &EvaluatedCall,
+    record: io::Result<FastaRecord>,
+    description: bool,
+    cols: &[String],
+) -> Result<Value, LabeledError> {
+    let r = record.map_err(|e| {
+        LabeledError::new("Record reading failed.")
+            .with_label(format!("cause of failure: {}", e), call.head)
+    })?;
 
-        let mut vec_vals = Vec::new();
+    let mut vec_vals = Vec::new();
 
-        vec_vals.push(call.head.with_string(r.name()));
+    vec_vals.push(call.head.with_string(r.name()));
 
-        if description {
-            vec_vals.push(call.head.with_string_or(r.description(), ""));
-        }
+    if description {
+        vec_vals.push(call.head.with_string_or(r.description(), ""));
+    }
 
-        vec_vals.push(call.head.with_string_from_utf8(r.sequence().as_ref()));
+    vec_vals.push(call.head.with_string_from_utf8(r.sequence().as_ref()));
 
-        let mut tmp_record = nu_protocol::Record::new();
-        for (col, val) in cols.clone().iter().zip(vec_vals) {
-            tmp_record.push(col, val);
-        }
-        value_records.push(Value::record(tmp_record, call.head))
+    let mut tmp_record = nu_protocol::Record::new();
+    for (col, val) in cols.iter().zip(vec_vals) {
+        tmp_record.push(col, val);
     }
-    Ok(())
+    Ok(Value::record(tmp_record, call.head))
 }
 
-/// Parse a fasta file into a nushell structure.
-pub fn from_fasta_inner(
+/// Parse a fasta file into a nushell structure, lazily.
+pub fn from_fasta_inner<R: BufRead + Send + 'static>(
     call: &EvaluatedCall,
-    input: &Value,
+    bytes: R,
     gz: Compression,
-) -> Result<Vec<Value>, LabeledError> {
+) -> Result<Records, LabeledError> {
     // parse description flag.
-    let description = call.has_flag("description");
-
-    let bytes = input.as_binary()?;
+    let description = call.has_flag("description")?;
 
     let reader = match gz {
         Compression::Uncompressed => FastaReader::Uncompressed(Box::new(fasta::Reader::new(bytes))),
@@ -195,18 +202,12 @@ pub fn from_fasta_inner(
         ],
     };
 
-    let mut value_records = Vec::new();
-
-    match reader {
-        FastaReader::Uncompressed(u) => {
-            iterate_fasta_records(*u, call, &mut value_records, description, cols)?
-        }
-        FastaReader::Compressed(c) => {
-            iterate_fasta_records(c, call, &mut value_records, description, cols)?
-        }
-    };
+    let call = call.clone();
 
-    Ok(value_records)
+    Ok(match reader {
+        FastaReader::Uncompressed(u) => iterate_fasta_records(*u, call, description, cols),
+        FastaReader::Compressed(c) => iterate_fasta_records(c, call, description, cols),
+    })
 }
 
 /// Go from a parsed nuon fasta structure to a string to stdout
@@ -218,30 +219,27 @@ pub fn nuon_to_fasta(call: &EvaluatedCall, input: &Value) -> Result<Value, Label
     if let Ok(list) = input.as_list() {
         for el in list {
             let inner = el.as_record()?;
-            let mut vals = inner.vals.clone();
+            let mut vals: Vec<Value> = inner.values().cloned().collect();
             let last = vals.pop().unwrap();
-            let sequence = last.as_string()?;
+            let sequence = last.coerce_string()?;
 
-            let id = vals.get(0).map(|e| e.as_string().unwrap());
-            let description = vals.get(1).map(|e| e.as_string().unwrap());
+            let id = vals.first().map(|e| e.coerce_string().unwrap());
+            let description = vals.get(1).map(|e| e.coerce_string().unwrap());
 
             let fa_def = FastaDefinition::new(id.unwrap_or("".into()), description);
             let fa_seq = Sequence::from(sequence.into_bytes());
 
             out.write_record(&FastaRecord::new(fa_def.clone(), fa_seq))
-                .map_err(|err| LabeledError {
-                    label: format!("Error in writing record ({}) to fasta", fa_def),
-                    msg: err.to_string(),
-                    span: Some(call.head),
+                .map_err(|err| {
+                    LabeledError::new(format!("Error in writing record ({}) to fasta", fa_def))
+                        .with_label(err.to_string(), call.head)
                 })?;
         }
     }
 
     let bytes = out.get_ref();
-    let out_final = String::from_utf8(bytes.clone()).map_err(|err| LabeledError {
-        label: "Can't format bytes as UTF-8".into(),
-        msg: err.to_string(),
-        span: Some(call.head),
+    let out_final = String::from_utf8(bytes.clone()).map_err(|err| {
+        LabeledError::new("Can't format bytes as UTF-8").with_label(err.to_string(), call.head)
     })?;
 
     Ok(Value::string(out_final, call.head))
@@ -256,49 +254,41 @@ pub fn nuon_to_fastq(call: &EvaluatedCall, input: &Value) -> Result<Value, Label
         let (description, quality) = match first {
             Some(e) => {
                 let first_inner = e.as_record()?;
-                let cols = first_inner.cols.clone();
                 (
-                    cols.contains(&String::from("description")),
-                    cols.contains(&String::from("quality_scores")),
+                    first_inner.contains("description"),
+                    first_inner.contains("quality_scores"),
                 )
             }
             None => {
                 // what's the error?
-                return Err(LabeledError {
-                    label: "No value".into(),
-                    msg: "There was no first value to call `to fastq` on".into(),
-                    span: Some(call.head),
-                });
+                return Err(LabeledError::new("No value")
+                    .with_label("There was no first value to call `to fastq` on", call.head));
             }
         };
 
         // if we don't have quality scores no point going further.
         if !quality {
-            return Err(LabeledError {
-                label: "No quality scores".into(),
-                msg: "Consider using `to fasta` if you don't have any quality scores, or pass the -q option on a fastq".into(),
-                span: Some(call.head),
-            });
+            return Err(LabeledError::new("No quality scores").with_label("Consider using `to fasta` if you don't have any quality scores, or pass the -q option on a fastq", call.head));
         }
 
         for el in list {
             let inner = el.as_record()?;
             // we need to check the columns.
-            let mut vals = inner.vals.clone();
+            let mut vals: Vec<Value> = inner.values().cloned().collect();
             let last = vals.pop().unwrap();
-            let sequence = last.as_string()?;
+            let sequence = last.coerce_string()?;
 
-            let id = vals.get(0).map(|e| e.as_string().unwrap());
+            let id = vals.first().map(|e| e.coerce_string().unwrap());
 
             let (d, q) = match (description, quality) {
                 (true, true) => {
                     // we got both
-                    let d = vals.get(1).map(|e| e.as_string().unwrap());
-                    let q = vals.get(2).map(|e| e.as_string().unwrap());
+                    let d = vals.get(1).map(|e| e.coerce_string().unwrap());
+                    let q = vals.get(2).map(|e| e.coerce_string().unwrap());
                     (d, q)
                 }
                 (false, true) => {
-                    let q = vals.get(1).map(|e| e.as_string().unwrap());
+                    let q = vals.get(1).map(|e| e.coerce_string().unwrap());
                     (None, q)
                 }
                 _ => unreachable!(),
@@ -311,20 +301,399 @@ pub fn nuon_to_fastq(call: &EvaluatedCall, input: &Value) -> Result<Value, Label
                 sequence.as_bytes(),
                 q.unwrap_or("".into()).as_bytes(),
             ))
-            .map_err(|err| LabeledError {
-                label: format!("Error in writing record ({:?}) to fastq", fq_def),
-                msg: err.to_string(),
-                span: Some(call.head),
+            .map_err(|err| {
+                LabeledError::new(format!("Error in writing record ({:?}) to fastq", fq_def))
+                    .with_label(err.to_string(), call.head)
             })?;
         }
     }
 
     let bytes = out.get_ref();
-    let out_final = String::from_utf8(bytes.clone()).map_err(|err| LabeledError {
-        label: "Can't format bytes as UTF-8".into(),
-        msg: err.to_string(),
-        span: Some(call.head),
+    let out_final = String::from_utf8(bytes.clone()).map_err(|err| {
+        LabeledError::new("Can't format bytes as UTF-8").with_label(err.to_string(), call.head)
     })?;
 
     Ok(Value::string(out_final, call.head))
 }
+
+/// Reverse complement a sequence. IUPAC codes are complemented, the case of
+/// each base is kept (so soft-masking survives), and anything else is left
+/// as it is.
+fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    sequence
+        .iter()
+        .rev()
+        .map(|&base| {
+            let complement = match base.to_ascii_uppercase() {
+                b'A' => b'T',
+                b'C' => b'G',
+                b'G' => b'C',
+                b'T' | b'U' => b'A',
+                b'M' => b'K',
+                b'K' => b'M',
+                b'R' => b'Y',
+                b'Y' => b'R',
+                b'B' => b'V',
+                b'V' => b'B',
+                b'D' => b'H',
+                b'H' => b'D',
+                other => other,
+            };
+            if base.is_ascii_lowercase() {
+                complement.to_ascii_lowercase()
+            } else {
+                complement
+            }
+        })
+        .collect()
+}
+
+/// A region to fetch, with the ID to give it in the output, and whether it
+/// is on the minus strand.
+struct FaidxRegion {
+    id: String,
+    region: Region,
+    minus: bool,
+}
+
+/// Turn a row from `from bed` into a region. Positions are already 1-based
+/// there, as they are in a region.
+fn bed_row_to_region(row: &Value, reverse_complement: bool) -> Result<FaidxRegion, LabeledError> {
+    let chrom = row.get_data_by_key("chrom");
+    let start = row.get_data_by_key("chromStart");
+    let end = row.get_data_by_key("chromEnd");
+
+    let (chrom, start, end) = match (chrom, start, end) {
+        (Some(chrom), Some(start), Some(end)) => {
+            (chrom.coerce_string()?, start.as_int()?, end.as_int()?)
+        }
+        _ => {
+            return Err(LabeledError::new("Input is not a BED table.").with_label(
+                "each row needs chrom, chromStart and chromEnd columns, as from `from bed`",
+                row.span(),
+            ))
+        }
+    };
+
+    let position = |p: i64| {
+        usize::try_from(p).ok().and_then(Position::new).ok_or(
+            LabeledError::new("Invalid BED interval.").with_label(
+                format!("{chrom}:{start}-{end} is not a valid interval"),
+                row.span(),
+            ),
+        )
+    };
+    let region = Region::new(chrom.clone(), position(start)?..=position(end)?);
+
+    let name = row
+        .get_data_by_key("name")
+        .and_then(|name| name.coerce_string().ok())
+        .filter(|name| !name.is_empty() && name != ".");
+    let minus = reverse_complement
+        && row
+            .get_data_by_key("strand")
+            .and_then(|strand| strand.coerce_string().ok())
+            .is_some_and(|strand| strand == "-");
+
+    Ok(FaidxRegion {
+        id: name.unwrap_or_else(|| region.to_string()),
+        region,
+        minus,
+    })
+}
+
+/// Fetch subsequences of an indexed FASTA (or BGZF FASTA, with a GZI index
+/// next to it), from regions given as arguments, or a BED table piped in.
+pub fn faidx_inner(call: &EvaluatedCall, input: &Value) -> Result<Vec<Value>, LabeledError> {
+    let path: Spanned<String> = call.req(0)?;
+    let raw_regions: Vec<Spanned<String>> = call.rest(1)?;
+    let reverse_complement_flag = call.has_flag("reverse-complement")?;
+
+    let mut regions = Vec::new();
+
+    for r in raw_regions {
+        let region = r.item.parse::<Region>().map_err(|e| {
+            LabeledError::new("Could not parse region.")
+                .with_label(format!("region {:?} is invalid: {e}", r.item), r.span)
+        })?;
+        regions.push(FaidxRegion {
+            id: r.item,
+            region,
+            minus: reverse_complement_flag,
+        });
+    }
+
+    if let Value::List { vals, .. } = input {
+        for row in vals {
+            regions.push(bed_row_to_region(row, reverse_complement_flag)?);
+        }
+    }
+
+    if regions.is_empty() {
+        return Err(LabeledError::new("No regions to fetch.").with_label(
+            "give at least one region, e.g. chr1:100-200, or pipe in a BED table",
+            call.head,
+        ));
+    }
+
+    let resolved = PathBuf::from(&path.item);
+    let index_path = find_index(call, &resolved, &[".fai"])?;
+
+    let index = fasta::fai::read(&index_path).map_err(|e| {
+        LabeledError::new("Could not read the FASTA index.")
+            .with_label(format!("cause of failure: {}", e), call.head)
+    })?;
+
+    let mut reader = fasta::indexed_reader::Builder::default()
+        .set_index(index)
+        .build_from_path(&resolved)
+        .map_err(|e| {
+            LabeledError::new(format!("Could not open {}.", resolved.display()))
+                .with_label(format!("cause of failure: {}", e), path.span)
+        })?;
+
+    let mut value_records = Vec::new();
+
+    for FaidxRegion { id, region, minus } in regions {
+        let record = reader.query(&region).map_err(|e| {
+            LabeledError::new(format!("Could not fetch region {region}."))
+                .with_label(format!("cause of failure: {}", e), call.head)
+        })?;
+
+        let sequence = record.sequence().as_ref();
+        let (id, sequence) = match minus {
+            // the same suffix as `samtools faidx --reverse-complement`.
+            true => (format!("{id}/rc"), reverse_complement(sequence)),
+            false => (id, sequence.to_vec()),
+        };
+
+        let mut tmp_record = nu_protocol::Record::new();
+        tmp_record.push("id", Value::string(id, call.head));
+        tmp_record.push("sequence", call.head.with_string_from_utf8(&sequence));
+        value_records.push(Value::record(tmp_record, call.head))
+    }
+
+    Ok(value_records)
+}
+
+/// The columns of a FAI index, as returned by `bio index fasta`.
+const FAI_COLUMNS: &[&str] = &["name", "length", "offset", "line_bases", "line_width"];
+
+/// Index the records of a FASTA. Offsets are in the uncompressed stream, so
+/// this works on both plain and bgzipped FASTA.
+fn index_fasta_records<R: BufRead>(mut reader: R) -> Result<Vec<fasta::fai::Record>, String> {
+    let mut records = Vec::new();
+    // name, length, offset, line_bases, line_width, and whether a short line was seen.
+    let mut current: Option<(String, u64, u64, u64, u64, bool)> = None;
+    let mut offset = 0;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let n = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| e.to_string())? as u64;
+        if n == 0 {
+            break;
+        }
+        offset += n;
+
+        if let Some(definition) = line.strip_prefix(b">") {
+            if let Some((name, length, offset, line_bases, line_width, _)) = current.take() {
+                records.push(fasta::fai::Record::new(
+                    name, length, offset, line_bases, line_width,
+                ));
+            }
+            let name = definition
+                .split(|b| b.is_ascii_whitespace())
+                .next()
+                .unwrap_or_default();
+            let name = String::from_utf8_lossy(name).to_string();
+            current = Some((name, 0, offset, 0, 0, false));
+            continue;
+        }
+
+        let bases = line.iter().filter(|b| !b.is_ascii_whitespace()).count() as u64;
+        // a last line without a newline has the width it would have with one, as in samtools.
+        let width = match line.ends_with(b"\n") {
+            true => n,
+            false => n + 1,
+        };
+
+        match current.as_mut() {
+            None if bases == 0 => continue,
+            None => return Err("sequence found before the first definition line".into()),
+            Some((name, length, _, line_bases, line_width, short)) => {
+                if bases == 0 {
+                    // empty lines end the sequence lines of this record.
+                    *short = true;
+                    continue;
+                }
+                if *length == 0 {
+                    *line_bases = bases;
+                    *line_width = width;
+                } else if *short
+                    || bases > *line_bases
+                    || (bases == *line_bases && width != *line_width)
+                {
+                    return Err(format!(
+                        "record {name} has sequence lines of different lengths"
+                    ));
+                }
+                if bases < *line_bases {
+                    *short = true;
+                }
+                *length += bases;
+            }
+        }
+    }
+
+    if let Some((name, length, offset, line_bases, line_width, _)) = current {
+        records.push(fasta::fai::Record::new(
+            name, length, offset, line_bases, line_width,
+        ));
+    }
+
+    Ok(records)
+}
+
+/// Build the GZI index of a BGZF file: the compressed and uncompressed
+/// offsets of the start of every block but the first.
+fn index_bgzf_blocks(path: &Path) -> io::Result<Vec<(u64, u64)>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let file_len = file.get_ref().metadata()?.len();
+
+    let mut offsets = Vec::new();
+    let (mut compressed, mut uncompressed) = (0, 0);
+    let mut header = [0; 12];
+
+    while compressed < file_len {
+        if compressed > 0 {
+            offsets.push((compressed, uncompressed));
+        }
+
+        file.seek(SeekFrom::Start(compressed))?;
+        file.read_exact(&mut header)?;
+        if header[..4] != [0x1f, 0x8b, 0x08, 0x04] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "file is not BGZF compressed, use bgzip rather than gzip",
+            ));
+        }
+
+        // the block size is in the BC subfield of the extra field.
+        let mut extra = vec![0; u16::from_le_bytes([header[10], header[11]]) as usize];
+        file.read_exact(&mut extra)?;
+        let mut block_size = None;
+        let mut subfields = extra.as_slice();
+        while subfields.len() >= 4 {
+            let len = u16::from_le_bytes([subfields[2], subfields[3]]) as usize;
+            if subfields[..2] == *b"BC" && len == 2 && subfields.len() >= 6 {
+                block_size = Some(u16::from_le_bytes([subfields[4], subfields[5]]) as u64 + 1);
+            }
+            subfields = subfields.get(4 + len..).unwrap_or_default();
+        }
+        let block_size = block_size.ok_or(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing BGZF block size",
+        ))?;
+
+        // the last 4 bytes of a block are the uncompressed size.
+        let mut isize = [0; 4];
+        file.seek(SeekFrom::Start(compressed + block_size - 4))?;
+        file.read_exact(&mut isize)?;
+
+        compressed += block_size;
+        uncompressed += u32::from_le_bytes(isize) as u64;
+    }
+
+    Ok(offsets)
+}
+
+/// Write a GZI index, in the same layout as `bgzip --index`.
+fn write_gzi(path: &Path, offsets: &[(u64, u64)]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&(offsets.len() as u64).to_le_bytes())?;
+    for (compressed, uncompressed) in offsets {
+        writer.write_all(&compressed.to_le_bytes())?;
+        writer.write_all(&uncompressed.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Build the `.fai` (and `.gzi`, if bgzipped) index of a FASTA, next to
+/// it, and return the rows of the FAI index. Existing indexes are only
+/// overwritten with `--force`.
+pub fn index_fasta_inner(call: &EvaluatedCall) -> Result<Vec<Value>, LabeledError> {
+    let path: Spanned<String> = call.req(0)?;
+    let resolved = PathBuf::from(&path.item);
+    let gzipped = path.item.ends_with(".gz") || path.item.ends_with(".bgz");
+
+    let with_extension = |ext: &str| {
+        let mut index = resolved.as_os_str().to_owned();
+        index.push(ext);
+        PathBuf::from(index)
+    };
+
+    let index_paths = match gzipped {
+        true => vec![with_extension(".fai"), with_extension(".gzi")],
+        false => vec![with_extension(".fai")],
+    };
+    if let Some(existing) = index_paths.iter().find(|index| index.exists()) {
+        if !call.has_flag("force")? {
+            return Err(LabeledError::new("The index already exists.").with_label(
+                format!("{} exists, use --force to overwrite it", existing.display()),
+                path.span,
+            ));
+        }
+    }
+
+    let file = File::open(&resolved).map_err(|e| {
+        LabeledError::new(format!("Could not open {}.", resolved.display()))
+            .with_label(format!("cause of failure: {}", e), path.span)
+    })?;
+
+    let records = match gzipped {
+        true => index_fasta_records(BufReader::new(bgzf::Reader::new(file))),
+        false => index_fasta_records(BufReader::new(file)),
+    }
+    .map_err(|e| LabeledError::new("Could not index the FASTA.").with_label(e, path.span))?;
+
+    let write_error = |e: io::Error| {
+        LabeledError::new("Could not write the index.")
+            .with_label(format!("cause of failure: {}", e), call.head)
+    };
+
+    if gzipped {
+        let offsets = index_bgzf_blocks(&resolved).map_err(|e| {
+            LabeledError::new("Could not index the BGZF blocks.")
+                .with_label(format!("cause of failure: {}", e), path.span)
+        })?;
+        write_gzi(&with_extension(".gzi"), &offsets).map_err(write_error)?;
+    }
+
+    File::create(with_extension(".fai"))
+        .map(BufWriter::new)
+        .map(fasta::fai::Writer::new)
+        .and_then(|mut writer| writer.write_index(&records))
+        .map_err(write_error)?;
+
+    let value_records = records
+        .iter()
+        .map(|r| {
+            let vals = vec![
+                call.head.with_string(r.name()),
+                Value::int(r.length() as i64, call.head),
+                Value::int(r.offset() as i64, call.head),
+                Value::int(r.line_bases() as i64, call.head),
+                Value::int(r.line_width() as i64, call.head),
+            ];
+            Value::record(
+                nu_protocol::Record::from_iter(FAI_COLUMNS.iter().map(|c| c.to_string()).zip(vals)),
+                call.head,
+            )
+        })
+        .collect();
+
+    Ok(value_records)
+}
@@ -6,17 +6,17 @@ use gfa::{
     optfields::{OptField, OptFieldVal},
     parser::GFAParser,
 };
-use nu_plugin::{EvaluatedCall, LabeledError};
-use nu_protocol::{record, Value};
-use std::io::{BufRead, BufReader};
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{record, LabeledError, Value};
+use std::io::BufRead;
 
 use super::{Compression, SpanExt};
 use noodles::bgzf;
 
 /// Compression status of a VCF reader.
-enum GFAReader<'a> {
-    Uncompressed(bstr::io::ByteLines<std::io::BufReader<&'a [u8]>>),
-    Compressed(bstr::io::ByteLines<bgzf::Reader<std::io::BufReader<&'a [u8]>>>),
+enum GFAReader<R> {
+    Uncompressed(bstr::io::ByteLines<R>),
+    Compressed(bstr::io::ByteLines<bgzf::Reader<R>>),
 }
 
 /// We do a lot of string conversion in this module,
@@ -27,10 +27,9 @@ fn string_from_utf8(
     call: &EvaluatedCall,
     context: &str,
 ) -> Result<String, LabeledError> {
-    String::from_utf8(inner).map_err(|e| LabeledError {
-        label: "Could convert bytes to string.".into(),
-        msg: format!("{}: {}", context, e),
-        span: Some(call.head),
+    String::from_utf8(inner).map_err(|e| {
+        LabeledError::new("Could convert bytes to string.")
+            .with_label(format!("{}: {}", context, e), call.head)
     })
 }
 
@@ -112,10 +111,9 @@ fn lines_to_nuon<R: BufRead>(
     call: &EvaluatedCall,
 ) -> Result<(), LabeledError> {
     for line in gfa_reader {
-        let line = line.map_err(|e| LabeledError {
-            label: "Could not read a line in the GFA.".into(),
-            msg: format!("cause of failure: {}", e),
-            span: Some(call.head),
+        let line = line.map_err(|e| {
+            LabeledError::new("Could not read a line in the GFA.")
+                .with_label(format!("cause of failure: {}", e), call.head)
         })?;
         // if this not added then
         if line.is_empty() {
@@ -243,31 +241,21 @@ fn lines_to_nuon<R: BufRead>(
             // I don't have access to the .tolerance field...
             // Err(err) if err.can_safely_continue(&parser.tolerance) => (),
             Err(e) => {
-                return Err(LabeledError {
-                    label: "Could not stream input as binary.".into(),
-                    msg: format!("cause of failure: {}", e),
-                    span: Some(call.head),
-                })
+                return Err(LabeledError::new("Could not stream input as binary.")
+                    .with_label(format!("cause of failure: {}", e), call.head))
             }
         };
     }
     Ok(())
 }
 
-pub fn from_gfa_inner(
+pub fn from_gfa_inner<R: BufRead>(
     call: &EvaluatedCall,
-    input: &Value,
+    reader: R,
     gz: Compression,
 ) -> Result<Value, LabeledError> {
     let parser: GFAParser<Vec<u8>, Vec<OptField>> = GFAParser::new();
 
-    let bytes = input.as_binary().map_err(|e| LabeledError {
-        label: "Value conversion to binary failed.".into(),
-        msg: format!("cause of failure: {}", e),
-        span: Some(call.head),
-    })?;
-
-    let reader = BufReader::new(bytes);
     let lines = match gz {
         Compression::Uncompressed => GFAReader::Uncompressed(reader.byte_lines()),
         Compression::Gzipped => GFAReader::Compressed(bgzf::Reader::new(reader).byte_lines()),
@@ -313,3 +301,138 @@ pub fn from_gfa_inner(
         call.head,
     ))
 }
+
+/// Get a string out of a GFA record, with missing values as an empty string.
+fn column_string(value: &Value, col: &str) -> Result<String, LabeledError> {
+    match value.get_data_by_key(col) {
+        Some(v) if !v.is_nothing() => Ok(v.coerce_string()?),
+        _ => Ok(String::new()),
+    }
+}
+
+/// Append the `optional_fields` of a GFA record to a line. These
+/// are stored as `TAG:TYPE:VALUE` strings by [`parse_optfieldval`].
+fn push_optional_fields(line: &mut Vec<String>, value: &Value) -> Result<(), LabeledError> {
+    if let Some(Value::List { vals, .. }) = value.get_data_by_key("optional_fields") {
+        for opt in vals {
+            // arrays are parsed with a trailing comma.
+            line.push(opt.coerce_string()?.trim_end_matches(',').to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Go from a parsed nuon GFA structure to a GFA 1.0 string.
+pub fn nuon_to_gfa(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    if !matches!(input, Value::Record { .. }) {
+        return Err(LabeledError::new("Input should be a record.").with_label(
+            format!(
+                "requires the output of `from gfa`, got {}",
+                input.get_type()
+            ),
+            call.head,
+        ));
+    }
+
+    let rows = |col: &str| -> Result<Vec<Value>, LabeledError> {
+        match input.get_data_by_key(col) {
+            Some(v) if !v.is_nothing() => Ok(v.as_list()?.to_vec()),
+            _ => Ok(Vec::new()),
+        }
+    };
+    // missing values are a star in GFA.
+    let or_star = |s: String| if s.is_empty() { "*".to_string() } else { s };
+
+    let mut lines = Vec::new();
+
+    // H
+    if let Some(header @ Value::Record { .. }) = input.get_data_by_key("header") {
+        let mut line = vec!["H".to_string()];
+        let version = column_string(&header, "version")?;
+        if !version.is_empty() && version != "No version specified" {
+            line.push(format!("VN:Z:{version}"));
+        }
+        push_optional_fields(&mut line, &header)?;
+        lines.push(line);
+    }
+
+    // S
+    for segment in rows("segments")? {
+        let mut line = vec![
+            "S".to_string(),
+            column_string(&segment, "name")?,
+            or_star(column_string(&segment, "sequence")?),
+        ];
+        push_optional_fields(&mut line, &segment)?;
+        lines.push(line);
+    }
+
+    // L
+    for link in rows("links")? {
+        let mut line = vec![
+            "L".to_string(),
+            column_string(&link, "from_segment")?,
+            column_string(&link, "from_orient")?,
+            column_string(&link, "to_segment")?,
+            column_string(&link, "to_orient")?,
+            or_star(column_string(&link, "overlaps")?),
+        ];
+        push_optional_fields(&mut line, &link)?;
+        lines.push(line);
+    }
+
+    // C
+    for containment in rows("containments")? {
+        let mut line = vec![
+            "C".to_string(),
+            column_string(&containment, "container_name")?,
+            column_string(&containment, "container_orient")?,
+            column_string(&containment, "containment_name")?,
+            column_string(&containment, "containment_orient")?,
+            column_string(&containment, "position")?,
+            or_star(column_string(&containment, "overlap")?),
+        ];
+        push_optional_fields(&mut line, &containment)?;
+        lines.push(line);
+    }
+
+    // P
+    for path in rows("paths")? {
+        let overlaps = match path.get_data_by_key("overlaps") {
+            Some(Value::List { vals, .. }) => vals
+                .iter()
+                .map(|e| e.coerce_string())
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .filter(|e| !e.is_empty())
+                .collect::<Vec<_>>()
+                .join(","),
+            Some(other) if !other.is_nothing() => other.coerce_string()?,
+            _ => String::new(),
+        };
+        let mut line = vec![
+            "P".to_string(),
+            column_string(&path, "path_name")?,
+            column_string(&path, "segment_names")?,
+            or_star(overlaps),
+        ];
+        push_optional_fields(&mut line, &path)?;
+        lines.push(line);
+    }
+
+    // check that we wrote valid GFA.
+    let parser: GFAParser<Vec<u8>, Vec<OptField>> = GFAParser::new();
+    let mut out = String::new();
+
+    for line in lines {
+        let line = line.join("\t");
+        parser.parse_gfa_line(line.as_bytes()).map_err(|e| {
+            LabeledError::new("Could not write a line in the GFA.")
+                .with_label(format!("line {line:?} is invalid: {e}"), call.head)
+        })?;
+        out.push_str(&line);
+        out.push('\n');
+    }
+
+    Ok(Value::string(out, call.head))
+}
@@ -1,9 +1,15 @@
 /// The GFF format
-use noodles::gff;
-use nu_plugin::{EvaluatedCall, LabeledError};
-use nu_protocol::{Record, Value};
+use noodles::gff::{
+    self,
+    record::{attributes::field::Value as AttributeValue, Attributes},
+    Directive,
+};
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{LabeledError, Record, Value};
+use std::{fmt::Display, io::BufRead, str::FromStr};
 
-use super::SpanExt;
+use super::fasta::nuon_to_fasta;
+use super::{Records, SpanExt};
 
 /// The GFF3 headers
 const GFF_COLUMNS: &[&str] = &[
@@ -18,11 +24,39 @@ const GFF_COLUMNS: &[&str] = &[
     "attributes",
 ];
 
-/// Add a GFF record
-fn add_record(call: &EvaluatedCall, r: gff::Record, vec_vals: &mut Vec<Value>) {
+/// Turn the GFF attributes into a record keyed by tag. Multi-valued
+/// tags (e.g. `Parent=a,b`) are lists, and values are percent-decoded.
+fn attributes_to_record(call: &EvaluatedCall, attributes: &Attributes) -> Value {
+    let record_inner = Record::from_iter(attributes.iter().map(|(tag, value)| {
+        let value = match value {
+            AttributeValue::String(s) => call.head.with_string(s),
+            AttributeValue::Array(vals) => Value::list(
+                vals.iter().map(|v| call.head.with_string(v)).collect(),
+                call.head,
+            ),
+        };
+        (tag.to_string(), value)
+    }));
+
+    Value::record(record_inner, call.head)
+}
+
+/// Add a GFF record. If `raw_attributes` is given, it is used as is
+/// for the attributes column instead of a record.
+fn add_record(
+    call: &EvaluatedCall,
+    r: gff::Record,
+    raw_attributes: Option<&str>,
+    vec_vals: &mut Vec<Value>,
+) {
     let start = usize::from(r.start());
     let end = usize::from(r.end());
 
+    let attributes = match raw_attributes {
+        Some(raw) => call.head.with_string(raw),
+        None => attributes_to_record(call, r.attributes()),
+    };
+
     let values_to_extend: Vec<Value> = vec![
         call.head.with_string(r.reference_sequence_name()),
         call.head.with_string(r.source()),
@@ -32,41 +66,222 @@ fn add_record(call: &EvaluatedCall, r: gff::Record, vec_vals: &mut Vec<Value>) {
         call.head.with_string_or(r.score(), ""),
         call.head.with_string(r.strand()),
         call.head.with_string_or(r.phase(), ""),
-        call.head.with_string(r.attributes()),
+        attributes,
     ];
 
     vec_vals.extend_from_slice(&values_to_extend);
 }
 
-/// Parse a fasta file into a nushell structure.
-pub fn from_gff_inner(call: &EvaluatedCall, input: &Value) -> Result<Vec<Value>, LabeledError> {
-    // match on file type
-    let stream = input.as_binary()?;
+/// Parse a GFF file into a nushell structure, lazily.
+pub fn from_gff_inner<R: BufRead + Send + 'static>(
+    call: &EvaluatedCall,
+    stream: R,
+) -> Result<Records, LabeledError> {
+    let raw_attributes = call.has_flag("raw-attributes")?;
 
     let mut reader = gff::Reader::new(stream);
+    let call = call.clone();
+
+    let mut buf = String::new();
+    let mut done = false;
 
-    let mut value_records = Vec::new();
-
-    for record in reader.records() {
-        let r = match record {
-            Ok(rec) => rec,
-            Err(e) => {
-                return Err(LabeledError {
-                    label: "Record reading failed.".into(),
-                    msg: format!("cause of failure: {}", e),
-                    span: Some(call.head),
-                })
+    // read line by line, so we can keep the attributes exactly as written.
+    Ok(Box::new(std::iter::from_fn(move || {
+        while !done {
+            buf.clear();
+            let n = match reader.read_line(&mut buf) {
+                Ok(n) => n,
+                Err(e) => {
+                    return Some(Err(LabeledError::new("Record reading failed.")
+                        .with_label(format!("cause of failure: {}", e), call.head)))
+                }
+            };
+
+            if n == 0 {
+                done = true;
+                break;
             }
-        };
+            if buf.is_empty() {
+                continue;
+            }
+
+            let r = match buf.parse::<gff::Line>() {
+                Ok(gff::Line::Record(rec)) => rec,
+                // sequences follow, and they are not records.
+                Ok(gff::Line::Directive(Directive::StartOfFasta)) => {
+                    done = true;
+                    break;
+                }
+                Ok(_) => continue,
+                Err(e) => {
+                    return Some(Err(LabeledError::new("Record reading failed.")
+                        .with_label(format!("cause of failure: {}", e), call.head)))
+                }
+            };
+
+            let raw = if raw_attributes {
+                buf.splitn(GFF_COLUMNS.len(), '\t').last()
+            } else {
+                None
+            };
+
+            let mut vec_vals = Vec::new();
+            add_record(&call, r, raw, &mut vec_vals);
 
-        let mut vec_vals = Vec::new();
-        add_record(call, r, &mut vec_vals);
+            let record_inner =
+                Record::from_iter(GFF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+
+            return Some(Ok(Value::record(record_inner, call.head)));
+        }
+
+        None
+    })))
+}
+
+/// Parse a column of a GFF row, treating missing values, empty strings
+/// and `.` as absent.
+fn parse_column<T>(row: &Value, col: &str) -> Result<Option<T>, LabeledError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = match row.get_data_by_key(col).filter(|v| !v.is_nothing()) {
+        Some(v) => v.coerce_string()?,
+        None => return Ok(None),
+    };
+
+    if s.is_empty() || s == "." {
+        return Ok(None);
+    }
+
+    s.parse().map(Some).map_err(|e| {
+        LabeledError::new(format!("Could not parse column `{col}`."))
+            .with_label(format!("value {s:?} is invalid: {e}"), row.span())
+    })
+}
+
+/// Turn the attributes column into GFF attributes. A record is keyed by tag,
+/// with lists for multi-valued tags, and a string is parsed as is.
+fn nuon_to_attributes(value: &Value) -> Result<Attributes, LabeledError> {
+    match value {
+        Value::Record { val, .. } => val
+            .iter()
+            .map(|(tag, v)| {
+                let attribute_value = match v {
+                    Value::List { vals, .. } => AttributeValue::from(
+                        vals.iter()
+                            .map(|e| e.coerce_string())
+                            .collect::<Result<Vec<_>, _>>()?,
+                    ),
+                    other => AttributeValue::from(other.coerce_string()?),
+                };
+                Ok((tag.clone(), attribute_value))
+            })
+            .collect(),
+        Value::Nothing { .. } => Ok(Attributes::default()),
+        other => {
+            let s = other.coerce_string()?;
+            if s.is_empty() || s == "." {
+                return Ok(Attributes::default());
+            }
+            s.parse().map_err(|e| {
+                LabeledError::new("Could not parse column `attributes`.")
+                    .with_label(format!("value {s:?} is invalid: {e}"), other.span())
+            })
+        }
+    }
+}
 
-        let record_inner =
-            Record::from_iter(GFF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+/// Turn a row with [`GFF_COLUMNS`] back into a GFF record.
+fn nuon_to_gff_record(row: &Value) -> Result<gff::Record, LabeledError> {
+    let mut builder = gff::Record::builder();
 
-        value_records.push(Value::record(record_inner, call.head))
+    if let Some(name) = parse_column(row, GFF_COLUMNS[0])? {
+        builder = builder.set_reference_sequence_name(name);
     }
+    if let Some(source) = parse_column(row, GFF_COLUMNS[1])? {
+        builder = builder.set_source(source);
+    }
+    if let Some(ty) = parse_column(row, GFF_COLUMNS[2])? {
+        builder = builder.set_type(ty);
+    }
+    if let Some(start) = parse_column(row, GFF_COLUMNS[3])? {
+        builder = builder.set_start(start);
+    }
+    if let Some(end) = parse_column(row, GFF_COLUMNS[4])? {
+        builder = builder.set_end(end);
+    }
+    if let Some(score) = parse_column(row, GFF_COLUMNS[5])? {
+        builder = builder.set_score(score);
+    }
+    if let Some(strand) = parse_column(row, GFF_COLUMNS[6])? {
+        builder = builder.set_strand(strand);
+    }
+    if let Some(phase) = parse_column(row, GFF_COLUMNS[7])? {
+        builder = builder.set_phase(phase);
+    }
+    if let Some(attributes) = row.get_data_by_key(GFF_COLUMNS[8]) {
+        builder = builder.set_attributes(nuon_to_attributes(&attributes)?);
+    }
+
+    Ok(builder.build())
+}
+
+/// Go from a parsed nuon GFF table to a GFF3 string.
+///
+/// The input is either a table of features, or a record with the features
+/// in `body` and a `from fasta` style table of `sequences`, which are written
+/// out in a `##FASTA` section.
+pub fn nuon_to_gff(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let separators = call.has_flag("separators")?;
+
+    let (body, sequences) = match input {
+        Value::List { .. } => (input.clone(), None),
+        Value::Record { .. } => match input.get_data_by_key("body") {
+            Some(body) => (body, input.get_data_by_key("sequences")),
+            None => {
+                return Err(LabeledError::new("Input record has no body.").with_label("requires a table of features, or a record with a `body` table and optional `sequences`", call.head))
+            }
+        },
+        other => {
+            return Err(LabeledError::new("Input should be a table or a record.").with_label(format!("requires a table of GFF features, got {}", other.get_type()), call.head))
+        }
+    };
+
+    let write_error = |err: std::io::Error| {
+        LabeledError::new("Error in writing GFF").with_label(err.to_string(), call.head)
+    };
+
+    let mut writer = gff::Writer::new(Vec::new());
+    writer
+        .write_directive(&Directive::GffVersion(Default::default()))
+        .map_err(write_error)?;
+
+    for (i, row) in body.as_list()?.iter().enumerate() {
+        let record = nuon_to_gff_record(row)?;
+
+        // top level features start a new group.
+        if separators && i > 0 && !record.attributes().contains_key("Parent") {
+            writer
+                .write_directive(&Directive::ForwardReferencesAreResolved)
+                .map_err(write_error)?;
+        }
+
+        writer.write_record(&record).map_err(write_error)?;
+    }
+
+    let mut out = writer.get_ref().clone();
+
+    if let Some(sequences) =
+        sequences.filter(|s| !s.as_list().map(|l| l.is_empty()).unwrap_or(true))
+    {
+        out.extend(format!("{}\n", Directive::StartOfFasta).as_bytes());
+        out.extend(nuon_to_fasta(call, &sequences)?.coerce_string()?.as_bytes());
+    }
+
+    let out_final = String::from_utf8(out).map_err(|err| {
+        LabeledError::new("Can't format bytes as UTF-8").with_label(err.to_string(), call.head)
+    })?;
 
-    Ok(value_records)
+    Ok(Value::string(out_final, call.head))
 }
@@ -0,0 +1,178 @@
+/// The GTF 2.2 format.
+/// It is the same as GFF2, but with a stricter attribute syntax:
+/// `gene_id "X"; transcript_id "Y";`
+use flate2::read::MultiGzDecoder;
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{LabeledError, Record, Value};
+use std::io::{BufRead, BufReader};
+
+use super::{Compression, Records, SpanExt};
+
+/// The GTF headers, the same as those from `from gff`.
+const GTF_COLUMNS: &[&str] = &[
+    "ref_seq_name",
+    "source",
+    "ty",
+    "start",
+    "end",
+    "score",
+    "strand",
+    "phase",
+    "attributes",
+];
+
+/// Attributes which must be present on every GTF record. Ensembl and
+/// GENCODE also write `gene` records, which have no `transcript_id`.
+const GTF_MANDATORY_ATTRIBUTES: &[&str] = &["gene_id", "transcript_id"];
+
+/// Split the attribute column into `(tag, value)` pairs. Values may be
+/// quoted, in which case the quotes are removed and `;` is allowed inside.
+fn split_attributes(s: &str) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = Vec::new();
+    let mut chars = s.trim().chars().peekable();
+
+    loop {
+        // skip separators between attributes.
+        while chars.peek().is_some_and(|c| c.is_whitespace() || *c == ';') {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let tag: String = chars.by_ref().take_while(|c| !c.is_whitespace()).collect();
+
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+
+        let value = if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => value.push(c),
+                    None => return Err(format!("unterminated quote in attribute {tag}")),
+                }
+            }
+            value
+        } else {
+            chars
+                .by_ref()
+                .take_while(|c| *c != ';')
+                .collect::<String>()
+                .trim()
+                .to_string()
+        };
+
+        pairs.push((tag, value));
+    }
+
+    Ok(pairs)
+}
+
+/// Parse the attributes into a record keyed by tag. Tags which occur more
+/// than once (e.g. `tag "basic"; tag "CCDS";`) become a list.
+fn parse_attributes(call: &EvaluatedCall, s: &str) -> Result<Record, String> {
+    let mut record = Record::new();
+
+    for (tag, value) in split_attributes(s)? {
+        let value = call.head.with_string(value);
+        match record.get_mut(&tag) {
+            Some(Value::List { vals, .. }) => vals.push(value),
+            Some(existing) => {
+                let first = existing.clone();
+                *existing = Value::list(vec![first, value], call.head);
+            }
+            None => record.push(tag, value),
+        }
+    }
+
+    Ok(record)
+}
+
+/// Parse a single GTF line into a row.
+fn parse_line(call: &EvaluatedCall, line: &str) -> Result<Vec<Value>, String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != GTF_COLUMNS.len() {
+        return Err(format!(
+            "expected {} tab separated columns, found {}",
+            GTF_COLUMNS.len(),
+            fields.len()
+        ));
+    }
+
+    let position = |col: usize| {
+        fields[col]
+            .parse::<i64>()
+            .map_err(|e| format!("invalid {} {:?}: {e}", GTF_COLUMNS[col], fields[col]))
+    };
+    // missing values are the same as `from gff`.
+    let optional = |col: usize| match fields[col] {
+        "." => call.head.with_string(""),
+        s => call.head.with_string(s),
+    };
+
+    let attributes = parse_attributes(call, fields[8])?;
+    for attribute in GTF_MANDATORY_ATTRIBUTES {
+        if *attribute == "transcript_id" && fields[2] == "gene" {
+            continue;
+        }
+        if !attributes.contains(attribute) {
+            return Err(format!("missing mandatory attribute {attribute}"));
+        }
+    }
+
+    Ok(vec![
+        call.head.with_string(fields[0]),
+        call.head.with_string(fields[1]),
+        call.head.with_string(fields[2]),
+        Value::int(position(3)?, call.head),
+        Value::int(position(4)?, call.head),
+        optional(5),
+        call.head.with_string(fields[6]),
+        optional(7),
+        Value::record(attributes, call.head),
+    ])
+}
+
+/// Iterate lazily over the lines of a GTF, skipping comments and empty
+/// lines.
+fn lines_to_nuon<R: BufRead + Send + 'static>(reader: R, call: EvaluatedCall) -> Records {
+    let lines = reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.is_empty() || line.starts_with('#')));
+
+    Box::new(lines.map(move |(index, line)| {
+        let line = line.map_err(|e| {
+            LabeledError::new("Line reading failed.")
+                .with_label(format!("cause of failure: {}", e), call.head)
+        })?;
+
+        let vec_vals = parse_line(&call, &line).map_err(|e| {
+            LabeledError::new(format!("Invalid GTF record on line {}.", index + 1))
+                .with_label(e, call.head)
+        })?;
+
+        let record_inner =
+            Record::from_iter(GTF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+
+        Ok(Value::record(record_inner, call.head))
+    }))
+}
+
+/// Parse a GTF file into a nushell structure, lazily.
+pub fn from_gtf_inner<R: BufRead + Send + 'static>(
+    call: &EvaluatedCall,
+    reader: R,
+    gz: Compression,
+) -> Result<Records, LabeledError> {
+    let call = call.clone();
+    Ok(match gz {
+        Compression::Uncompressed => lines_to_nuon(reader, call),
+        // GENCODE and Ensembl write plain gzip, not BGZF.
+        Compression::Gzipped => lines_to_nuon(BufReader::new(MultiGzDecoder::new(reader)), call),
+    })
+}
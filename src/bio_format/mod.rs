@@ -1,4 +1,10 @@
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{record, LabeledError, ListStream, PipelineData, RawStream, Spanned};
 pub use nu_protocol::{Span, Value};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Read, Seek},
+};
 /// SAM + BAM parsing facility.
 pub mod bam;
 /// BCF + VCF parsing facility.
@@ -13,6 +19,10 @@ pub mod fasta;
 pub mod gfa;
 /// GFF(3) parsing facility
 pub mod gff;
+/// GTF parsing facility
+pub mod gtf;
+/// Region queries on indexed files
+pub mod query;
 
 /// Compression enum
 pub enum Compression {
@@ -20,6 +30,177 @@ pub enum Compression {
     Gzipped,
 }
 
+/// Open the file at the optional path argument of a `from <fmt>` command.
+fn open_path(call: &EvaluatedCall) -> Result<Option<File>, LabeledError> {
+    match call.opt::<Spanned<String>>(0)? {
+        Some(path) => File::open(&path.item).map(Some).map_err(|e| {
+            LabeledError::new(format!("Could not open {}.", path.item))
+                .with_label(format!("cause of failure: {}", e), path.span)
+        }),
+        None => Ok(None),
+    }
+}
+
+/// [`Read`] over the bytes of a raw stream piped in to a command, as from
+/// `open`, pulling the chunks of the stream as they are needed.
+struct RawStreamReader {
+    stream: RawStream,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl RawStreamReader {
+    fn new(mut stream: RawStream) -> Self {
+        let chunk = std::mem::take(&mut stream.leftover);
+        Self {
+            stream,
+            chunk,
+            position: 0,
+        }
+    }
+}
+
+impl Read for RawStreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.stream.stream.next() {
+                Some(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Some(Err(e)) => return Err(io::Error::other(e.to_string())),
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.position);
+        buf[..n].copy_from_slice(&self.chunk[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// The input piped in to a `from <fmt>` command, either a raw stream, read
+/// as it is parsed, or a binary value.
+enum PipedInput {
+    Stream(RawStreamReader),
+    Bytes(Vec<u8>),
+}
+
+/// The input piped in to a `from <fmt>` command.
+fn piped_input(call: &EvaluatedCall, input: PipelineData) -> Result<PipedInput, LabeledError> {
+    match input {
+        PipelineData::ExternalStream {
+            stdout: Some(stdout),
+            ..
+        } => Ok(PipedInput::Stream(RawStreamReader::new(stdout))),
+        input => input
+            .into_value(call.head)
+            .coerce_into_binary()
+            .map(PipedInput::Bytes)
+            .map_err(|e| {
+                LabeledError::new("Value conversion to binary failed.")
+                    .with_label(format!("cause of failure: {}", e), call.head)
+            }),
+    }
+}
+
+/// The input of a `from <fmt>` command: the file at the optional path
+/// argument, or otherwise the input piped in, read through a buffer as it
+/// is parsed.
+pub fn input_reader(
+    call: &EvaluatedCall,
+    input: PipelineData,
+) -> Result<Box<dyn BufRead + Send>, LabeledError> {
+    if let Some(file) = open_path(call)? {
+        return Ok(Box::new(BufReader::new(file)));
+    }
+
+    match piped_input(call, input)? {
+        PipedInput::Stream(stream) => Ok(Box::new(BufReader::new(stream))),
+        PipedInput::Bytes(bytes) => Ok(Box::new(Cursor::new(bytes))),
+    }
+}
+
+/// A buffered reader which can also seek.
+pub trait SeekBufRead: BufRead + Seek + Send {}
+
+impl<T: BufRead + Seek + Send> SeekBufRead for T {}
+
+/// As [`input_reader`], with the length of the input in bytes, for the
+/// formats which check what is left of the input as they read it. A stream
+/// cannot seek, so one piped in is read whole first.
+pub fn input_seekable_reader(
+    call: &EvaluatedCall,
+    input: PipelineData,
+) -> Result<(Box<dyn SeekBufRead>, u64), LabeledError> {
+    let read_error = |e: io::Error| {
+        LabeledError::new("Could not read the input.")
+            .with_label(format!("cause of failure: {}", e), call.head)
+    };
+
+    if let Some(file) = open_path(call)? {
+        let length = file.metadata().map_err(read_error)?.len();
+        return Ok((Box::new(BufReader::new(file)), length));
+    }
+
+    let bytes = match piped_input(call, input)? {
+        PipedInput::Stream(mut stream) => {
+            let mut bytes = Vec::new();
+            stream.read_to_end(&mut bytes).map_err(read_error)?;
+            bytes
+        }
+        PipedInput::Bytes(bytes) => bytes,
+    };
+    let length = bytes.len() as u64;
+    Ok((Box::new(Cursor::new(bytes)), length))
+}
+
+/// The rows of a table parsed by a `from <fmt>` command, read lazily from
+/// the input as they are asked for.
+pub type Records = Box<dyn Iterator<Item = Result<Value, LabeledError>> + Send>;
+
+/// Stream records to nushell. An error ends the stream, as the last item.
+pub fn into_list_stream(records: Records, span: Span) -> PipelineData {
+    let mut failed = false;
+    let values = records.map_while(move |record| match record {
+        _ if failed => None,
+        Ok(value) => Some(value),
+        Err(e) => {
+            failed = true;
+            Some(Value::error(e.into(), span))
+        }
+    });
+    PipelineData::ListStream(ListStream::from_stream(values, None), None)
+}
+
+/// The header of a file and its records, as a record of `header` and
+/// `body`.
+pub fn header_and_body(header: Value, body: Records, span: Span) -> Result<Value, LabeledError> {
+    Ok(Value::record(
+        record! {
+            "header" => header,
+            "body" => Value::list(body.collect::<Result<_, _>>()?, span),
+        },
+        span,
+    ))
+}
+
+/// The output of a `from <fmt>` command for a format with a header: a
+/// record of the `header` and `body`, or with `--body`, only the records of
+/// the body, streamed.
+pub fn header_and_body_output(
+    call: &EvaluatedCall,
+    header: Value,
+    body: Records,
+) -> Result<PipelineData, LabeledError> {
+    if call.has_flag("body")? {
+        Ok(into_list_stream(body, call.head))
+    } else {
+        header_and_body(header, body, call.head).map(|value| PipelineData::Value(value, None))
+    }
+}
+
 pub trait SpanExt {
     fn with_string<S: ToString>(&self, s: S) -> Value;
     fn with_string_or<S: ToString>(&self, s: Option<S>, default: &str) -> Value;
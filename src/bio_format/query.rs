@@ -0,0 +1,183 @@
+/// Region queries on indexed files, which read only the parts of the
+/// file overlapping the regions, rather than the whole file.
+use noodles::{
+    core::Region,
+    csi::{self, io::IndexedRecord},
+    tabix,
+};
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{LabeledError, Spanned, Value};
+use std::{
+    fs::File,
+    io::{BufRead, Cursor},
+    path::{Path, PathBuf},
+};
+
+use super::bam::query_bam;
+use super::bcf::from_vcf_inner;
+use super::bed::from_bed_inner;
+use super::gff::from_gff_inner;
+use super::{header_and_body, Compression};
+
+/// The BGZF-compressed, tabix-indexed formats which can be queried.
+enum TabixFormat {
+    Vcf,
+    Bed,
+    Gff,
+}
+
+impl TabixFormat {
+    fn from_path(path: &str) -> Option<Self> {
+        let path = path
+            .strip_suffix(".gz")
+            .or_else(|| path.strip_suffix(".bgz"))?;
+
+        if path.ends_with(".vcf") {
+            Some(Self::Vcf)
+        } else if path.ends_with(".bed") {
+            Some(Self::Bed)
+        } else if path.ends_with(".gff") || path.ends_with(".gff3") {
+            Some(Self::Gff)
+        } else {
+            None
+        }
+    }
+}
+
+/// Find the index of a file, either given with `--index`, or next to the
+/// file with one of the `extensions`.
+pub fn find_index(
+    call: &EvaluatedCall,
+    path: &Path,
+    extensions: &[&str],
+) -> Result<PathBuf, LabeledError> {
+    if let Some(index) = call.get_flag::<String>("index")? {
+        return Ok(PathBuf::from(index));
+    }
+
+    extensions
+        .iter()
+        .map(|ext| {
+            let mut index = path.as_os_str().to_owned();
+            index.push(ext);
+            PathBuf::from(index)
+        })
+        .find(|index| index.exists())
+        .ok_or(LabeledError::new("Could not find an index.").with_label(
+            format!(
+                "no {} index next to {}, give one with --index",
+                extensions.join(" or "),
+                path.display()
+            ),
+            call.head,
+        ))
+}
+
+/// Query a sorted, indexed file for the regions given, e.g. `chr1:100-200`.
+pub fn query_inner(call: &EvaluatedCall) -> Result<Value, LabeledError> {
+    let path: Spanned<String> = call.req(0)?;
+    let raw_regions: Vec<Spanned<String>> = call.rest(1)?;
+
+    if raw_regions.is_empty() {
+        return Err(LabeledError::new("No regions to query.")
+            .with_label("give at least one region, e.g. chr1:100-200", call.head));
+    }
+
+    let regions = raw_regions
+        .iter()
+        .map(|r| {
+            r.item.parse::<Region>().map_err(|e| {
+                LabeledError::new("Could not parse region.")
+                    .with_label(format!("region {:?} is invalid: {e}", r.item), r.span)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if path.item.ends_with(".bam") {
+        query_bam(call, Path::new(&path.item), &regions)
+    } else if let Some(format) = TabixFormat::from_path(&path.item) {
+        query_tabix(call, Path::new(&path.item), &regions, format)
+    } else {
+        Err(LabeledError::new("Unsupported file type for a query.").with_label("only indexed BAM (.bam), and bgzipped VCF, BED or GFF (.vcf.gz, .bed.gz, .gff.gz) files can be queried", path.span))
+    }
+}
+
+/// Query a bgzipped, tabix-indexed text file. The overlapping lines are
+/// collected and passed to the same parser as `from vcf`, `from bed` or
+/// `from gff`, so the rows are the same as from those commands. A line
+/// overlapping more than one region is only returned for the first of them.
+fn query_tabix(
+    call: &EvaluatedCall,
+    path: &Path,
+    regions: &[Region],
+    format: TabixFormat,
+) -> Result<Value, LabeledError> {
+    let index_path = find_index(call, path, &[".tbi", ".csi"])?;
+
+    let index = if index_path.extension().is_some_and(|ext| ext == "csi") {
+        csi::read(&index_path)
+    } else {
+        tabix::read(&index_path)
+    }
+    .map_err(|e| {
+        LabeledError::new("Could not read the index.")
+            .with_label(format!("cause of failure: {}", e), call.head)
+    })?;
+
+    let mut reader = File::open(path)
+        .map(|f| csi::io::IndexedReader::new(f, index))
+        .map_err(|e| {
+            LabeledError::new(format!("Could not open {}.", path.display()))
+                .with_label(format!("cause of failure: {}", e), call.head)
+        })?;
+
+    let mut bytes = Vec::new();
+
+    // a VCF cannot be parsed without its header, which is at the start of the file.
+    if let TabixFormat::Vcf = format {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let n = reader.get_mut().read_until(b'\n', &mut line).map_err(|e| {
+                LabeledError::new("Could not read the VCF header.")
+                    .with_label(format!("cause of failure: {}", e), call.head)
+            })?;
+            if n == 0 || !line.starts_with(b"#") {
+                break;
+            }
+            bytes.extend_from_slice(&line);
+        }
+    }
+
+    for (i, region) in regions.iter().enumerate() {
+        let query_error = |e: std::io::Error| {
+            LabeledError::new(format!("Could not query region {region}."))
+                .with_label(format!("cause of failure: {}", e), call.head)
+        };
+
+        for record in reader.query(region).map_err(query_error)? {
+            let record = record.map_err(query_error)?;
+            let seen = regions[..i].iter().any(|seen| {
+                record.indexed_reference_sequence_name() == seen.name()
+                    && record.indexed_interval().intersects(seen.interval())
+            });
+            if seen {
+                continue;
+            }
+            bytes.extend_from_slice(record.as_ref().as_bytes());
+            bytes.push(b'\n');
+        }
+    }
+
+    let bytes = Cursor::new(bytes);
+    let records = match format {
+        TabixFormat::Vcf => {
+            let (header, body) = from_vcf_inner(call, bytes, Compression::Uncompressed)?;
+            return header_and_body(header, body, call.head);
+        }
+        TabixFormat::Bed => from_bed_inner(call, bytes)?,
+        TabixFormat::Gff => from_gff_inner(call, bytes)?,
+    };
+
+    Ok(Value::list(records.collect::<Result<_, _>>()?, call.head))
+}
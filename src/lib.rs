@@ -1,4 +1,6 @@
 //! A bioinformatics parsing library for nushell.
+// `LabeledError` is the error type of the plugin interface.
+#![allow(clippy::result_large_err)]
 
 /// Where the core parsers live.
 mod bio;
@@ -2,5 +2,5 @@ use nu_plugin::{serve_plugin, JsonSerializer};
 use nu_plugin_bio::Bio;
 
 fn main() {
-    serve_plugin(&mut Bio {}, JsonSerializer {})
+    serve_plugin(&Bio {}, JsonSerializer {})
 }
@@ -1,45 +1,129 @@
 use crate::bio_format::Compression;
 use crate::Bio;
-use nu_plugin::{EvaluatedCall, LabeledError, Plugin};
-use nu_protocol::{Category, PluginSignature, Value};
+use nu_plugin::{EngineInterface, EvaluatedCall, Plugin, PluginCommand};
+use nu_protocol::{Category, LabeledError, PipelineData, Signature, SyntaxShape, Value};
+use std::path::Path;
+
+/// One command of the plugin, described by its signature and run by [`Bio::run`].
+pub struct BioCommand(Signature);
+
+impl PluginCommand for BioCommand {
+    type Plugin = Bio;
+
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    fn signature(&self) -> Signature {
+        self.0.clone()
+    }
+
+    fn usage(&self) -> &str {
+        &self.0.usage
+    }
+
+    fn run(
+        &self,
+        plugin: &Bio,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let call = resolve_paths(&self.0, engine, call)?;
+        plugin.run(self.name(), &call, input)
+    }
+}
+
+// the plugin runs in its own process, so relative paths are resolved
+// against the directory of the shell that called it.
+fn resolve_paths(
+    signature: &Signature,
+    engine: &EngineInterface,
+    call: &EvaluatedCall,
+) -> Result<EvaluatedCall, LabeledError> {
+    let mut call = call.clone();
+    let cwd = engine.get_current_dir()?;
+    let resolve = |value: &mut Value| {
+        if let Value::String { val, .. } = value {
+            *val = Path::new(&cwd).join(&*val).to_string_lossy().into_owned();
+        }
+    };
+
+    let positional_shapes = signature
+        .required_positional
+        .iter()
+        .chain(&signature.optional_positional)
+        .map(|arg| &arg.shape);
+    for (value, shape) in call.positional.iter_mut().zip(positional_shapes) {
+        if *shape == SyntaxShape::Filepath {
+            resolve(value);
+        }
+    }
+    for (name, value) in call.named.iter_mut() {
+        let is_path = signature
+            .named
+            .iter()
+            .any(|flag| flag.long == name.item && flag.arg == Some(SyntaxShape::Filepath));
+        if let (true, Some(value)) = (is_path, value) {
+            resolve(value);
+        }
+    }
+
+    Ok(call)
+}
 
 impl Plugin for Bio {
-    fn signature(&self) -> Vec<PluginSignature> {
-        vec![
-            PluginSignature::build("from fasta")
+    fn commands(&self) -> Vec<Box<dyn PluginCommand<Plugin = Self>>> {
+        signatures()
+            .into_iter()
+            .map(|signature| {
+                Box::new(BioCommand(signature)) as Box<dyn PluginCommand<Plugin = Self>>
+            })
+            .collect()
+    }
+}
+
+fn signatures() -> Vec<Signature> {
+    vec![
+            Signature::build("from fasta")
                 .usage("Parse a fasta file.\nReturns a table of ID's and sequences.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
                 .switch(
                     "description",
                     "parse the fasta header description",
                     Some('d'),
                 )
                 .category(Category::Experimental),
-            PluginSignature::build("from fasta.gz")
+            Signature::build("from fasta.gz")
                 .usage("Parse a gzipped fasta file.\nReturns a table of ID's and sequences.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
                 .switch(
                     "description",
                     "parse the fasta header description",
                     Some('d'),
                 )
                 .category(Category::Experimental),
-            PluginSignature::build("from fa")
+            Signature::build("from fa")
                 .usage("Parse a fasta file.\nReturns a table of ID's and sequences.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
                 .switch(
                     "description",
                     "parse the fasta header description",
                     Some('d'),
                 )
                 .category(Category::Experimental),
-            PluginSignature::build("from fa.gz")
+            Signature::build("from fa.gz")
                 .usage("Parse a gzipped fasta file.\nReturns a table of ID's and sequences.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
                 .switch(
                     "description",
                     "parse the fasta header description",
                     Some('d'),
                 )
                 .category(Category::Experimental),
-            PluginSignature::build("from fastq")
+            Signature::build("from fastq")
                 .usage("Parse a fastq file.\nReturns a table of ID's and sequences.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
                 .switch(
                     "description",
                     "parse the fastq header description",
@@ -51,8 +135,9 @@ impl Plugin for Bio {
                     Some('q'),
                 )
                 .category(Category::Experimental),
-            PluginSignature::build("from fastq.gz")
+            Signature::build("from fastq.gz")
                 .usage("Parse a gzipped fastq file.\nReturns a table of ID's and sequences.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
                 .switch(
                     "description",
                     "parse the fastq header description",
@@ -64,10 +149,11 @@ impl Plugin for Bio {
                     Some('q'),
                 )
                 .category(Category::Experimental),
-            PluginSignature::build("to fasta")
+            Signature::build("to fasta")
                 .usage("Print a parsed fasta object to a string"),
-            PluginSignature::build("from fq")
+            Signature::build("from fq")
                 .usage("Parse a fastq file.\nReturns a table of ID's and sequences.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
                 .switch(
                     "description",
                     "parse the fastq header description",
@@ -79,8 +165,9 @@ impl Plugin for Bio {
                     Some('q'),
                 )
                 .category(Category::Experimental),
-            PluginSignature::build("from fq.gz")
+            Signature::build("from fq.gz")
                 .usage("Parse a gzipped fastq file.\nReturns a table of ID's and sequences.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
                 .switch(
                     "description",
                     "parse the fastq header description",
@@ -92,50 +179,210 @@ impl Plugin for Bio {
                     Some('q'),
                 )
                 .category(Category::Experimental),
-            PluginSignature::build("to fastq")
+            Signature::build("to fastq")
                 .usage("Print out a fastq from structured nuon"),
-            PluginSignature::build("from bam")
+            Signature::build("from bam")
                 .usage("Parse a BAM file.\nReturns a record containing the header and the body of the BAM file.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
+                .switch(
+                    "decode-flags",
+                    "add a decoded_flags column of named booleans",
+                    Some('d'),
+                )
+                .switch(
+                    "body",
+                    "return only the body, streamed record by record",
+                    Some('b'),
+                )
                 .category(Category::Experimental),
-            PluginSignature::build("from sam")
+            Signature::build("to bam")
+                .usage("Write the structured output of `from sam` or `from bam` to BGZF-compressed BAM bytes"),
+            Signature::build("from sam")
                 .usage("Parse a SAM file.\nReturns a record containing the header and the body of the SAM file.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
+                .switch(
+                    "decode-flags",
+                    "add a decoded_flags column of named booleans",
+                    Some('d'),
+                )
+                .switch(
+                    "body",
+                    "return only the body, streamed record by record",
+                    Some('b'),
+                )
                 .category(Category::Experimental),
-            PluginSignature::build("from cram")
+            Signature::build("to sam")
+                .usage("Print out a SAM from the structured output of `from sam` or `from bam`"),
+            Signature::build("bio query")
+                .usage("Read the records of a sorted, indexed file overlapping one or more regions.\nBAM files need a BAI or CSI index, bgzipped VCF, BED and GFF files a tabix (TBI) or CSI index.\nBAM and VCF return a record containing the header and the body, BED and GFF a table.\nA record overlapping more than one region is returned once.")
+                .required("path", SyntaxShape::Filepath, "the sorted BAM, or bgzipped VCF, BED or GFF file")
+                .rest("regions", SyntaxShape::String, "regions to query, e.g. chr1:100-200")
+                .named(
+                    "index",
+                    SyntaxShape::Filepath,
+                    "the index (default: the path with .bai or .csi added for BAM, .tbi or .csi otherwise)",
+                    Some('i'),
+                )
+                .category(Category::Experimental),
+            Signature::build("bio faidx")
+                .usage("Fetch subsequences of a FASTA with a .fai index (and a .gzi index if bgzipped).\nRegions are given as arguments, or as a BED table piped in.\nReturns a table of ID's and sequences.")
+                .required("path", SyntaxShape::Filepath, "the FASTA file")
+                .rest("regions", SyntaxShape::String, "regions to fetch, e.g. chr1:100-200")
+                .switch(
+                    "reverse-complement",
+                    "reverse complement the regions (only minus strand rows of a BED table)",
+                    Some('r'),
+                )
+                .named(
+                    "index",
+                    SyntaxShape::Filepath,
+                    "the FAI index (default: the path with .fai added)",
+                    Some('i'),
+                )
+                .category(Category::Experimental),
+            Signature::build("bio index fasta")
+                .usage("Index a FASTA, writing a .fai index (and a .gzi index if bgzipped) next to it.\nReturns a table of the index rows.")
+                .required("path", SyntaxShape::Filepath, "the FASTA file")
+                .switch(
+                    "force",
+                    "overwrite the index if it exists",
+                    Some('f'),
+                )
+                .category(Category::Experimental),
+            Signature::build("from cram")
                 .usage("Parse a CRAM file into SAM output.\nReturns a record containing the header and the body of the CRAM file.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
+                .switch(
+                    "decode-flags",
+                    "add a decoded_flags column of named booleans",
+                    Some('d'),
+                )
+                .named(
+                    "reference",
+                    SyntaxShape::Filepath,
+                    "the reference FASTA (with a .fai index), to restore the read sequences",
+                    Some('r'),
+                )
+                .switch(
+                    "body",
+                    "return only the body, streamed record by record",
+                    Some('b'),
+                )
                 .category(Category::Experimental),
-            PluginSignature::build("from bcf")
+            Signature::build("to cram")
+                .usage("Write the structured output of `from sam`, `from bam` or `from cram` to CRAM 3.0 bytes.\nMapped records need quality scores.")
+                .required_named(
+                    "reference",
+                    SyntaxShape::Filepath,
+                    "the reference FASTA (with a .fai index) to compress the reads against",
+                    Some('r'),
+                )
+                .category(Category::Experimental),
+            Signature::build("from bcf")
                 .usage("Parse a BCF file.\nReturns a record containing the header and the body of the BCF file.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
+                .switch(
+                    "body",
+                    "return only the body, streamed record by record",
+                    Some('b'),
+                )
                 .category(Category::Experimental),
-            PluginSignature::build("from bcf.gz")
+            Signature::build("from bcf.gz")
                 .usage("Parse a gzipped BCF file.\nReturns a record containing the header and the body of the BCF file.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
+                .switch(
+                    "body",
+                    "return only the body, streamed record by record",
+                    Some('b'),
+                )
                 .category(Category::Experimental),
-            PluginSignature::build("from vcf")
+            Signature::build("to bcf")
+                .usage("Write the structured output of `from vcf` or `from bcf` to BCF bytes"),
+            Signature::build("from vcf")
                 .usage("Parse a VCF file.\nReturns a record containing the header and the body of the VCF file.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
+                .switch(
+                    "body",
+                    "return only the body, streamed record by record",
+                    Some('b'),
+                )
                 .category(Category::Experimental),
-            PluginSignature::build("from vcf.gz")
+            Signature::build("from vcf.gz")
                 .usage("Parse a gzipped VCF file.\nReturns a record containing the header and the body of the VCF file.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
+                .switch(
+                    "body",
+                    "return only the body, streamed record by record",
+                    Some('b'),
+                )
+                .category(Category::Experimental),
+            Signature::build("to vcf")
+                .usage("Print out a VCF from the structured output of `from vcf` or `from bcf`"),
+            Signature::build("to vcf.gz")
+                .usage("Write the structured output of `from vcf` or `from bcf` to BGZF-compressed VCF bytes"),
+            Signature::build("bio vcf long")
+                .usage("Melt the output of `from vcf` or `from bcf` into one row per variant and sample, with the GT and FORMAT fields as columns")
                 .category(Category::Experimental),
-            PluginSignature::build("from gff")
-                .usage("Parse a GFF file.\nReturns a table.")
+            Signature::build("from gff")
+                .usage("Parse a GFF file.\nReturns a table, with the attributes as a record.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
+                .switch(
+                    "raw-attributes",
+                    "keep the attributes as the original string, for lossless round trips",
+                    Some('r'),
+                )
                 .category(Category::Experimental),
-            PluginSignature::build("from gfa")
+            Signature::build("to gff")
+                .usage("Print out a GFF3 from a table of features, or a record with a `body` of features and `sequences`")
+                .switch(
+                    "separators",
+                    "write a ### directive before each top level feature",
+                    Some('s'),
+                ),
+            Signature::build("from gtf")
+                .usage("Parse a GTF 2.2 file, with the attributes as a record.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
+                .category(Category::Experimental),
+            Signature::build("from gtf.gz")
+                .usage("Parse a bgzipped GTF 2.2 file, with the attributes as a record.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
+                .category(Category::Experimental),
+            Signature::build("from gfa")
                 .usage("Parse a GFA file.\nReturns a record containing the header, segments, links, containments, and paths.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
                 .category(Category::Experimental),
-            PluginSignature::build("from gfa.gz")
+            Signature::build("from gfa.gz")
                 .usage("Parse a gzipped GFA file.\nReturns a record containing the header, segments, links, containments, and paths.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
                 .category(Category::Experimental),
-            PluginSignature::build("from bed")
-                .usage("Parse a BED file.")
-                .category(Category::Experimental)
-        ]
-    }
+            Signature::build("to gfa")
+                .usage("Print out a GFA 1.0 from the structured output of `from gfa`"),
+            Signature::build("from bed")
+                .usage("Parse a BED3 to BED12 file, extra columns are kept in a list.")
+                .optional("path", SyntaxShape::Filepath, "read the file at this path, rather than the input")
+                .named(
+                    "columns",
+                    SyntaxShape::Int,
+                    "number of standard BED columns to parse (default: detected from the first record)",
+                    Some('c'),
+                )
+                .category(Category::Experimental),
+            Signature::build("to bed")
+                .usage("Print out a BED3 to BED12 from a table with the standard BED columns"),
+    ]
+}
 
-    fn run(
-        &mut self,
+impl Bio {
+    /// Run the command called `name`.
+    pub fn run(
+        &self,
         name: &str,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        // only the `from` commands read their input as a stream
+        let value = |input: PipelineData| input.into_value(call.head);
+        let output = |value: Value| PipelineData::Value(value, None);
         match name {
             "from fasta" => self.from_fasta(call, input, Compression::Uncompressed),
             "from fa" => self.from_fasta(call, input, Compression::Uncompressed),
@@ -145,24 +392,35 @@ impl Plugin for Bio {
             "from fa.gz" => self.from_fasta(call, input, Compression::Gzipped),
             "from fastq.gz" => self.from_fastq(call, input, Compression::Gzipped),
             "from fq.gz" => self.from_fastq(call, input, Compression::Gzipped),
-            "to fasta" => self.to_fasta(call, input),
-            "to fastq" => self.to_fastq(call, input),
+            "to fasta" => self.to_fasta(call, &value(input)).map(output),
+            "to fastq" => self.to_fastq(call, &value(input)).map(output),
             "from bam" => self.from_bam(call, input),
+            "to bam" => self.to_bam(call, &value(input)).map(output),
             "from sam" => self.from_sam(call, input),
+            "to sam" => self.to_sam(call, &value(input)).map(output),
+            "bio query" => self.query(call).map(output),
+            "bio faidx" => self.faidx(call, &value(input)).map(output),
+            "bio index fasta" => self.index_fasta(call).map(output),
             "from cram" => self.from_cram(call, input),
+            "to cram" => self.to_cram(call, &value(input)).map(output),
             "from bcf" => self.from_bcf(call, input, Compression::Uncompressed),
             "from bcf.gz" => self.from_bcf(call, input, Compression::Gzipped),
+            "to bcf" => self.to_bcf(call, &value(input)).map(output),
             "from vcf" => self.from_vcf(call, input, Compression::Uncompressed),
             "from vcf.gz" => self.from_vcf(call, input, Compression::Gzipped),
+            "to vcf" => self.to_vcf(call, &value(input), Compression::Uncompressed).map(output),
+            "to vcf.gz" => self.to_vcf(call, &value(input), Compression::Gzipped).map(output),
+            "bio vcf long" => self.vcf_long(call, &value(input)).map(output),
             "from gff" => self.from_gff(call, input),
+            "to gff" => self.to_gff(call, &value(input)).map(output),
+            "from gtf" => self.from_gtf(call, input, Compression::Uncompressed),
+            "from gtf.gz" => self.from_gtf(call, input, Compression::Gzipped),
             "from gfa" => self.from_gfa(call, input, Compression::Uncompressed),
             "from gfa.gz" => self.from_gfa(call, input, Compression::Gzipped),
-            "from bed" => self.from_bed(call, input.clone()),
-            _ => Err(LabeledError {
-                label: "Plugin call with wrong name signature".into(),
-                msg: "the signature used to call the plugin does not match any name in the plugin signature vector".into(),
-                span: Some(call.head),
-            }),
+            "to gfa" => self.to_gfa(call, &value(input)).map(output),
+            "from bed" => self.from_bed(call, input),
+            "to bed" => self.to_bed(call, &value(input)).map(output),
+            _ => Err(LabeledError::new("Plugin call with wrong name signature").with_label("the signature used to call the plugin does not match any name in the plugin signature vector", call.head)),
         }
     }
 }
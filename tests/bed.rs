@@ -0,0 +1,212 @@
+mod common;
+
+use common::*;
+use nu_protocol::{record, Span, Value};
+
+fn int(i: i64) -> Value {
+    Value::int(i, Span::unknown())
+}
+
+fn string(s: &str) -> Value {
+    Value::string(s, Span::unknown())
+}
+
+fn ints(is: &[i64]) -> Value {
+    Value::list(is.iter().map(|i| int(*i)).collect(), Span::unknown())
+}
+
+/// A BED12 row, as `from bed` gives it, with 1-based starts.
+fn bed12_row(name: &str) -> Value {
+    Value::record(
+        record! {
+            "chrom" => string("chr1"),
+            "chromStart" => int(1),
+            "chromEnd" => int(100),
+            "name" => string(name),
+            "score" => int(5),
+            "strand" => string("+"),
+            "thickStart" => int(11),
+            "thickEnd" => int(90),
+            "itemRgb" => string("255,0,0"),
+            "blockCount" => int(2),
+            "blockSizes" => ints(&[10, 20]),
+            "blockStarts" => ints(&[0, 80]),
+        },
+        Span::unknown(),
+    )
+}
+
+fn table(rows: Vec<Value>) -> Value {
+    Value::list(rows, Span::unknown())
+}
+
+#[test]
+fn to_bed_round_trips_bed3() {
+    let bed = "chr1\t0\t100\nchr2\t10\t20\n";
+    assert_eq!(text(&run("to bed", &run("from bed", &binary(bed)))), bed);
+}
+
+#[test]
+fn to_bed_writes_bed12() {
+    let out = text(&run("to bed", &table(vec![bed12_row("a"), bed12_row("b")])));
+    assert_eq!(
+        out,
+        "chr1\t0\t100\ta\t5\t+\t10\t90\t255,0,0\t2\t10,20\t0,80\n\
+         chr1\t0\t100\tb\t5\t+\t10\t90\t255,0,0\t2\t10,20\t0,80\n"
+    );
+}
+
+#[test]
+fn to_bed_rejects_rows_of_another_width() {
+    let bed3 = run("from bed", &binary("chr1\t0\t100\n"));
+    let rows = table(vec![bed12_row("a"), bed3.as_list().unwrap()[0].clone()]);
+    assert!(try_run_with("to bed", &call(&[], &[]), &rows).is_err());
+}
+
+#[test]
+fn to_bed_checks_values() {
+    let mut row = bed12_row("a");
+    if let Value::Record { val, .. } = &mut row {
+        *val.get_mut("score").unwrap() = int(1001);
+    }
+    let err = try_run_with("to bed", &call(&[], &[]), &table(vec![row])).unwrap_err();
+    assert_eq!(err.msg, "Invalid BED row at index 0.");
+    assert!(
+        err.labels[0].text.contains("score"),
+        "{}",
+        err.labels[0].text
+    );
+}
+
+#[test]
+fn from_bed_reads_bed6() {
+    let bed = "chr1\t0\t100\tgene1\t0\t+\nchr1\t10\t20\t.\t500\t-\n";
+    let rows = run("from bed", &binary(bed));
+    let rows = rows.as_list().unwrap();
+    assert_eq!(
+        rows[0]
+            .as_record()
+            .unwrap()
+            .columns()
+            .cloned()
+            .collect::<Vec<_>>()
+            .len(),
+        6
+    );
+    assert_eq!(column(rows, "chromStart")[1].as_int().unwrap(), 11);
+    assert!(column(rows, "name")[1].is_nothing());
+    assert_eq!(column(rows, "strand")[1].coerce_string().unwrap(), "-");
+    assert_eq!(
+        text(&run("to bed", &Value::list(rows.to_vec(), Span::unknown()))),
+        bed
+    );
+}
+
+#[test]
+fn from_bed_reads_ucsc_bed12() {
+    let bed = "browser position chr1:1-1000\n\
+        track name=genes description=\"some genes\"\n\
+        #comment\n\
+        chr1\t0\t100\tgene1\t0\t+\t10\t90\t255,0,0\t2\t10,20,\t0,80,\n\
+        chr2\t10\t20\tgene2\t500\t-\t10\t20\t0\t1\t10,\t0,\n";
+    let rows = run("from bed", &binary(bed));
+    let rows = rows.as_list().unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(
+        format!("{:?}", column(rows, "blockSizes")[0]),
+        format!("{:?}", ints(&[10, 20]))
+    );
+    assert_eq!(
+        format!("{:?}", column(rows, "blockStarts")[1]),
+        format!("{:?}", ints(&[0]))
+    );
+    assert_eq!(
+        text(&run("to bed", &Value::list(rows.to_vec(), Span::unknown()))),
+        "chr1\t0\t100\tgene1\t0\t+\t10\t90\t255,0,0\t2\t10,20\t0,80\n\
+         chr2\t10\t20\tgene2\t500\t-\t10\t20\t0\t1\t10\t0\n"
+    );
+}
+
+#[test]
+fn from_bed_reports_the_line_of_a_bad_row() {
+    let bed = "track name=x\nchr1\t0\t100\tgene1\t0\t+\nchr1\t10\t20\n";
+    let err = try_run_with("from bed", &call(&[], &[]), &binary(bed)).unwrap_err();
+    assert!(err.msg.contains("line 3"), "{}", err.msg);
+}
+
+#[test]
+fn from_bed_columns_keeps_the_rest_as_extra() {
+    let bed = "chr1\t0\t100\tgene1\t0\t+\n";
+    let columns = call_with_values(&[], &[("columns", Some(int(4)))]);
+    let rows = run_with("from bed", &columns, &binary(bed));
+    let rows = rows.as_list().unwrap();
+    assert_eq!(
+        rows[0]
+            .as_record()
+            .unwrap()
+            .columns()
+            .cloned()
+            .collect::<Vec<_>>(),
+        ["chrom", "chromStart", "chromEnd", "name", "extra"]
+    );
+    assert_eq!(
+        format!("{:?}", column(rows, "extra")[0]),
+        format!(
+            "{:?}",
+            Value::list(vec![string("0"), string("+")], Span::unknown())
+        )
+    );
+    assert_eq!(
+        text(&run("to bed", &Value::list(rows.to_vec(), Span::unknown()))),
+        bed
+    );
+
+    let bad = call_with_values(&[], &[("columns", Some(int(10)))]);
+    assert!(try_run_with("from bed", &bad, &binary(bed)).is_err());
+}
+
+#[test]
+fn from_bed_reads_a_path() {
+    let dir = temp_dir("from_bed_reads_a_path");
+    let path = dir.join("regions.bed");
+    let bed = "chr1\t10\t20\tfirst\t0\t+\nchr1\t30\t40\tsecond\t0\t-\n";
+    std::fs::write(&path, bed).unwrap();
+
+    let from_path = run_with(
+        "from bed",
+        &call(&[path.to_str().unwrap()], &[]),
+        &Value::nothing(Span::unknown()),
+    );
+    assert_eq!(
+        format!("{:?}", from_path),
+        format!("{:?}", run("from bed", &binary(bed)))
+    );
+}
+
+#[test]
+fn from_bed_detects_the_width_past_the_header() {
+    // more header than fits in the buffer of a file reader.
+    let mut bed = "# comment\n".repeat(2000);
+    bed.push_str("track name=regions\nchr1\t10\t20\tfirst\t0\t+\n");
+
+    let dir = temp_dir("from_bed_detects_the_width_past_the_header");
+    let path = dir.join("regions.bed");
+    std::fs::write(&path, &bed).unwrap();
+
+    let rows = run_with(
+        "from bed",
+        &call(&[path.to_str().unwrap()], &[]),
+        &Value::nothing(Span::unknown()),
+    );
+    let rows = rows.as_list().unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(column(rows, "strand")[0].coerce_string().unwrap(), "+");
+
+    let err = try_run_with(
+        "from bed",
+        &call(&[], &[]),
+        &binary(b"chr1\t\xff\t20\n".to_vec()),
+    )
+    .unwrap_err();
+    assert_eq!(err.msg, "BED input is not UTF-8.");
+}
@@ -0,0 +1,157 @@
+//! Helpers shared by the integration tests, which call the plugin as
+//! nushell would.
+#![allow(dead_code, clippy::result_large_err)]
+
+use nu_plugin::EvaluatedCall;
+use nu_plugin_bio::Bio;
+use nu_protocol::{LabeledError, PipelineData, RawStream, Span, Spanned, Value};
+use std::path::{Path, PathBuf};
+
+/// A call with string positional arguments and named flags. Flags
+/// without a value are switches.
+pub fn call(positional: &[&str], named: &[(&str, Option<&str>)]) -> EvaluatedCall {
+    let string = |s: &str| Value::string(s, Span::unknown());
+    call_with_values(
+        &positional.iter().map(|p| string(p)).collect::<Vec<_>>(),
+        &named
+            .iter()
+            .map(|(name, value)| (*name, value.map(string)))
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// A call with any values as arguments.
+pub fn call_with_values(positional: &[Value], named: &[(&str, Option<Value>)]) -> EvaluatedCall {
+    EvaluatedCall {
+        head: Span::unknown(),
+        positional: positional.to_vec(),
+        named: named
+            .iter()
+            .map(|(name, value)| {
+                (
+                    Spanned {
+                        item: name.to_string(),
+                        span: Span::unknown(),
+                    },
+                    value.clone(),
+                )
+            })
+            .collect(),
+    }
+}
+
+/// Run a command on any pipeline input, returning its output unread.
+pub fn try_run_piped(
+    name: &str,
+    call: &EvaluatedCall,
+    input: PipelineData,
+) -> Result<PipelineData, LabeledError> {
+    Bio {}.run(name, call, input)
+}
+
+/// Run a command, returning its error, including one that ended a stream.
+pub fn try_run_with(
+    name: &str,
+    call: &EvaluatedCall,
+    input: &Value,
+) -> Result<Value, LabeledError> {
+    let output = try_run_piped(name, call, PipelineData::Value(input.clone(), None))?;
+    collect(output)
+}
+
+/// Read a command's output whole, returning the error that ended a stream.
+pub fn collect(output: PipelineData) -> Result<Value, LabeledError> {
+    let value = output.into_value(Span::unknown());
+    if let Value::List { vals, .. } = &value {
+        if let Some(Value::Error { error, .. }) = vals.last() {
+            return Err(LabeledError::from(*error.clone()));
+        }
+    }
+    Ok(value)
+}
+
+/// Run a command, panicking with its error.
+pub fn run_with(name: &str, call: &EvaluatedCall, input: &Value) -> Value {
+    try_run_with(name, call, input).unwrap_or_else(|e| panic!("`{name}` failed: {e:?}"))
+}
+
+/// Run a command with no arguments.
+pub fn run(name: &str, input: &Value) -> Value {
+    run_with(name, &call(&[], &[]), input)
+}
+
+/// The path of a file in `tests/`.
+pub fn fixture_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(name)
+}
+
+/// The bytes of a file in `tests/`.
+pub fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(fixture_path(name)).unwrap_or_else(|e| panic!("reading {name}: {e}"))
+}
+
+/// Binary input, as from `open --raw`.
+pub fn binary(bytes: impl Into<Vec<u8>>) -> Value {
+    Value::binary(bytes.into(), Span::unknown())
+}
+
+/// Bytes piped from an external command, arriving in chunks of `chunk` bytes.
+pub fn piped(bytes: &[u8], chunk: usize) -> PipelineData {
+    let chunks: Vec<_> = bytes.chunks(chunk).map(<[u8]>::to_vec).collect();
+    raw_stream(chunks.into_iter())
+}
+
+/// The output of an external command, as it produces it.
+pub fn raw_stream(chunks: impl Iterator<Item = Vec<u8>> + Send + 'static) -> PipelineData {
+    PipelineData::ExternalStream {
+        stdout: Some(RawStream::new(
+            Box::new(chunks.map(Ok)),
+            None,
+            Span::unknown(),
+            None,
+        )),
+        stderr: None,
+        exit_code: None,
+        span: Span::unknown(),
+        metadata: None,
+        trim_end_newline: false,
+    }
+}
+
+/// A fresh scratch directory for a test.
+pub fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nu_plugin_bio-{}-{test}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The text of a string value, or of a binary one.
+pub fn text(value: &Value) -> String {
+    match value {
+        Value::Binary { val, .. } => String::from_utf8(val.clone()).unwrap(),
+        other => other.coerce_string().unwrap(),
+    }
+}
+
+/// The body of a `{header, body}` record.
+pub fn body(value: &Value) -> Vec<Value> {
+    value
+        .get_data_by_key("body")
+        .unwrap()
+        .as_list()
+        .unwrap()
+        .to_vec()
+}
+
+/// A column of every row of a table.
+pub fn column(rows: &[Value], col: &str) -> Vec<Value> {
+    rows.iter()
+        .map(|r| {
+            r.get_data_by_key(col)
+                .unwrap_or_else(|| panic!("no column {col}"))
+        })
+        .collect()
+}
@@ -0,0 +1,235 @@
+mod common;
+
+use common::*;
+use nu_protocol::{Span, Value};
+
+/// `from cram` on map.cram, with drAilAlti1.fa as the reference.
+fn from_cram_with_reference() -> Value {
+    let reference = fixture_path("drAilAlti1.fa");
+    run_with(
+        "from cram",
+        &call(&[], &[("reference", Some(reference.to_str().unwrap()))]),
+        &binary(fixture("map.cram")),
+    )
+}
+
+#[test]
+fn from_cram_restores_sequences_from_the_reference() {
+    let bam = body(&run("from bam", &binary(fixture("map.bam"))));
+
+    // without the reference, the mapped reads have no bases.
+    let without = body(&run("from cram", &binary(fixture("map.cram"))));
+    assert_eq!(without.len(), 100);
+    for row in &without {
+        let mapped = row.get_data_by_key("flags").unwrap().as_int().unwrap() & 4 == 0;
+        let sequence = row
+            .get_data_by_key("sequence")
+            .unwrap()
+            .coerce_string()
+            .unwrap();
+        assert_eq!(sequence.is_empty(), mapped);
+    }
+
+    let with = body(&from_cram_with_reference());
+    assert_eq!(
+        format!("{:?}", column(&with, "read_name")),
+        format!("{:?}", column(&bam, "read_name"))
+    );
+    assert_eq!(
+        format!("{:?}", column(&with, "sequence")),
+        format!("{:?}", column(&bam, "sequence"))
+    );
+}
+
+#[test]
+fn from_cram_checks_the_reference_md5() {
+    let dir = temp_dir("from_cram_checks_the_reference_md5");
+    let reference = dir.join("drAilAlti1.fa");
+
+    // the same length and layout, so the index still fits, but one base differs.
+    let mut fasta = fixture("drAilAlti1.fa");
+    fasta[12] = if fasta[12] == b'A' { b'C' } else { b'A' };
+    std::fs::write(&reference, fasta).unwrap();
+    std::fs::write(dir.join("drAilAlti1.fa.fai"), fixture("drAilAlti1.fa.fai")).unwrap();
+
+    let err = try_run_with(
+        "from cram",
+        &call(&[], &[("reference", Some(reference.to_str().unwrap()))]),
+        &binary(fixture("map.cram")),
+    )
+    .unwrap_err();
+    assert_eq!(err.msg, "Reference MD5 mismatch.");
+    assert!(err.labels[0]
+        .text
+        .contains("8af0c1d53341751f0e27f9397139252b"));
+}
+
+#[test]
+fn from_cram_needs_an_indexed_reference() {
+    let dir = temp_dir("from_cram_needs_an_indexed_reference");
+    let reference = dir.join("drAilAlti1.fa");
+    std::fs::write(&reference, fixture("drAilAlti1.fa")).unwrap();
+
+    let err = try_run_with(
+        "from cram",
+        &call(&[], &[("reference", Some(reference.to_str().unwrap()))]),
+        &binary(fixture("map.cram")),
+    )
+    .unwrap_err();
+    assert_eq!(err.msg, "Could not open the reference.");
+}
+
+#[test]
+fn from_cram_reports_truncation() {
+    let cram = fixture("map.cram");
+    let from_cram = |length: usize| {
+        try_run_with("from cram", &call(&[], &[]), &binary(&cram[..length])).unwrap_err()
+    };
+
+    // the CRAM 3.0 EOF container is the last 38 bytes.
+    let err = from_cram(cram.len() - 38);
+    assert_eq!(err.msg, "CRAM file is truncated at container 4.");
+    assert_eq!(
+        err.labels[0].text,
+        "the input ended without an EOF container"
+    );
+
+    let err = from_cram(cram.len() - 30);
+    assert_eq!(err.msg, "CRAM file is truncated at container 4.");
+    assert_eq!(
+        err.labels[0].text,
+        "the container is 15 bytes long, but only 8 bytes are left"
+    );
+
+    let err = from_cram(cram.len() - 100);
+    assert_eq!(err.msg, "CRAM file is truncated at container 3.");
+
+    let err = from_cram(600);
+    assert_eq!(err.msg, "CRAM file is truncated at container 0.");
+
+    assert_eq!(from_cram(200).msg, "CRAM file header reading failed.");
+    assert_eq!(from_cram(10).msg, "Could not read CRAM file definition.");
+}
+
+/// map.bam, with a quality score for every base, as CRAM needs them.
+fn map_bam_with_qualities() -> Value {
+    let mut bam = run("from bam", &binary(fixture("map.bam")));
+    if let Value::Record { val, .. } = &mut bam {
+        if let Value::List { vals, .. } = val.get_mut("body").unwrap() {
+            for row in vals {
+                if let Value::Record { val: row, .. } = row {
+                    let length = row.get("sequence").unwrap().coerce_string().unwrap().len();
+                    *row.get_mut("quality_scores").unwrap() =
+                        Value::string("I".repeat(length), Span::unknown());
+                }
+            }
+        }
+    }
+    bam
+}
+
+#[test]
+fn to_cram_round_trips() {
+    let bam = map_bam_with_qualities();
+    let reference = fixture_path("drAilAlti1.fa");
+    let with_reference = call(&[], &[("reference", Some(reference.to_str().unwrap()))]);
+
+    let cram = run_with("to cram", &with_reference, &bam);
+    let again = body(&run_with("from cram", &with_reference, &cram));
+
+    for col in [
+        "read_name",
+        "flags",
+        "reference_sequence_name",
+        "alignment_start",
+        "cigar",
+        "sequence",
+        "quality_scores",
+        "data",
+    ] {
+        assert_eq!(
+            format!("{:?}", column(&again, col)),
+            format!("{:?}", column(&body(&bam), col)),
+            "column {col}"
+        );
+    }
+
+    // CRAM only keeps the mapping quality of mapped reads.
+    let mapped = |rows: &[Value]| -> Vec<Value> {
+        rows.iter()
+            .filter(|r| r.get_data_by_key("flags").unwrap().as_int().unwrap() & 4 == 0)
+            .cloned()
+            .collect()
+    };
+    assert_eq!(
+        format!("{:?}", column(&mapped(&again), "mapping_quality")),
+        format!("{:?}", column(&mapped(&body(&bam)), "mapping_quality"))
+    );
+}
+
+#[test]
+fn to_cram_needs_quality_scores() {
+    let bam = run("from bam", &binary(fixture("map.bam")));
+    let reference = fixture_path("drAilAlti1.fa");
+
+    let err = try_run_with(
+        "to cram",
+        &call(&[], &[("reference", Some(reference.to_str().unwrap()))]),
+        &bam,
+    )
+    .unwrap_err();
+    assert_eq!(err.msg, "Error in writing record (sequence-1) to CRAM");
+    assert_eq!(
+        err.labels[0].text,
+        "the record has no quality scores, which mapped records need in CRAM"
+    );
+}
+
+#[test]
+fn from_cram_reads_a_path() {
+    let path = fixture_path("map.cram");
+    let from_path = run_with(
+        "from cram",
+        &call(&[path.to_str().unwrap()], &[]),
+        &Value::nothing(Span::unknown()),
+    );
+    assert_eq!(
+        format!("{:?}", from_path),
+        format!("{:?}", run("from cram", &binary(fixture("map.cram"))))
+    );
+}
+
+#[test]
+fn from_cram_reports_truncation_of_a_path() {
+    let cram = fixture("map.cram");
+    let dir = temp_dir("from_cram_reports_truncation_of_a_path");
+    let path = dir.join("truncated.cram");
+    std::fs::write(&path, &cram[..cram.len() - 30]).unwrap();
+
+    let err = try_run_with(
+        "from cram",
+        &call(&[path.to_str().unwrap()], &[]),
+        &Value::nothing(Span::unknown()),
+    )
+    .unwrap_err();
+    assert_eq!(err.msg, "CRAM file is truncated at container 4.");
+    assert_eq!(
+        err.labels[0].text,
+        "the container is 15 bytes long, but only 8 bytes are left"
+    );
+}
+
+#[test]
+fn from_cram_reads_piped_bytes() {
+    let cram = fixture("map.cram");
+    let output = try_run_piped(
+        "from cram",
+        &call(&[], &[("body", None)]),
+        piped(&cram, 100),
+    )
+    .unwrap();
+    assert_eq!(
+        format!("{:?}", collect(output).unwrap().as_list().unwrap()),
+        format!("{:?}", body(&run("from cram", &binary(cram))))
+    );
+}
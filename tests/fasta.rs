@@ -0,0 +1,269 @@
+mod common;
+
+use common::*;
+use nu_protocol::{PipelineData, Span, Value};
+
+/// The whole sequence of drAilAlti1.fa.
+fn genome() -> String {
+    let fasta = run("from fasta", &binary(fixture("drAilAlti1.fa")));
+    fasta.as_list().unwrap()[0]
+        .get_data_by_key("sequence")
+        .unwrap()
+        .coerce_string()
+        .unwrap()
+}
+
+/// The id and sequence of each row of `bio faidx`.
+fn faidx(args: &[&str], named: &[(&str, Option<&str>)], input: &Value) -> Vec<(String, String)> {
+    let path = fixture_path("drAilAlti1.fa");
+    let mut positional = vec![path.to_str().unwrap()];
+    positional.extend(args);
+    run_with("bio faidx", &call(&positional, named), input)
+        .as_list()
+        .unwrap()
+        .iter()
+        .map(|row| {
+            (
+                row.get_data_by_key("id").unwrap().coerce_string().unwrap(),
+                row.get_data_by_key("sequence")
+                    .unwrap()
+                    .coerce_string()
+                    .unwrap(),
+            )
+        })
+        .collect()
+}
+
+/// Reverse complement an (upper case, ACGT) sequence.
+fn revcomp(sequence: &str) -> String {
+    sequence
+        .chars()
+        .rev()
+        .map(|base| match base {
+            'A' => 'T',
+            'C' => 'G',
+            'G' => 'C',
+            'T' => 'A',
+            other => other,
+        })
+        .collect()
+}
+
+#[test]
+fn faidx_fetches_regions() {
+    let genome = genome();
+    let nothing = Value::nothing(Span::unknown());
+    let rows = faidx(
+        &["drAilAlti1:1-10", "drAilAlti1:19700-19709"],
+        &[],
+        &nothing,
+    );
+
+    assert_eq!(
+        rows,
+        [
+            ("drAilAlti1:1-10".to_string(), genome[..10].to_string()),
+            (
+                "drAilAlti1:19700-19709".to_string(),
+                genome[19699..].to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn faidx_reverse_complements() {
+    let genome = genome();
+    let nothing = Value::nothing(Span::unknown());
+    let rows = faidx(
+        &["drAilAlti1:101-120"],
+        &[("reverse-complement", None)],
+        &nothing,
+    );
+
+    assert_eq!(
+        rows,
+        [(
+            "drAilAlti1:101-120/rc".to_string(),
+            revcomp(&genome[100..120])
+        )]
+    );
+}
+
+#[test]
+fn faidx_takes_a_bed_table() {
+    let genome = genome();
+    let bed = run(
+        "from bed",
+        &binary("drAilAlti1\t0\t10\tfirst\t0\t+\ndrAilAlti1\t100\t120\tsecond\t0\t-\n"),
+    );
+    let rows = faidx(&[], &[("reverse-complement", None)], &bed);
+
+    // BED intervals are 0-based and half-open, and only minus strand rows
+    // are reverse complemented.
+    assert_eq!(
+        rows,
+        [
+            ("first".to_string(), genome[..10].to_string()),
+            ("second/rc".to_string(), revcomp(&genome[100..120])),
+        ]
+    );
+}
+
+#[test]
+fn faidx_needs_a_region() {
+    let path = fixture_path("drAilAlti1.fa");
+    let err = try_run_with(
+        "bio faidx",
+        &call(&[path.to_str().unwrap()], &[]),
+        &Value::nothing(Span::unknown()),
+    )
+    .unwrap_err();
+    assert_eq!(err.msg, "No regions to fetch.");
+}
+
+/// Run `bio index fasta` on a path.
+#[allow(clippy::result_large_err)]
+fn index_fasta(
+    path: &std::path::Path,
+    named: &[(&str, Option<&str>)],
+) -> Result<Value, nu_protocol::LabeledError> {
+    try_run_with(
+        "bio index fasta",
+        &call(&[path.to_str().unwrap()], named),
+        &Value::nothing(Span::unknown()),
+    )
+}
+
+#[test]
+fn index_fasta_writes_the_fai() {
+    let dir = temp_dir("index_fasta_writes_the_fai");
+    let fasta = dir.join("drAilAlti1.fa");
+    std::fs::write(&fasta, fixture("drAilAlti1.fa")).unwrap();
+
+    let rows = index_fasta(&fasta, &[]).unwrap();
+    assert_eq!(
+        std::fs::read(dir.join("drAilAlti1.fa.fai")).unwrap(),
+        fixture("drAilAlti1.fa.fai")
+    );
+    let row = &rows.as_list().unwrap()[0];
+    assert_eq!(
+        row.get_data_by_key("name")
+            .unwrap()
+            .coerce_string()
+            .unwrap(),
+        "drAilAlti1"
+    );
+    assert_eq!(
+        row.get_data_by_key("length").unwrap().as_int().unwrap(),
+        19709
+    );
+}
+
+#[test]
+fn index_fasta_keeps_an_existing_index() {
+    let dir = temp_dir("index_fasta_keeps_an_existing_index");
+    let fasta = dir.join("drAilAlti1.fa");
+    let fai = dir.join("drAilAlti1.fa.fai");
+    std::fs::write(&fasta, fixture("drAilAlti1.fa")).unwrap();
+    std::fs::write(&fai, "old").unwrap();
+
+    let err = index_fasta(&fasta, &[]).unwrap_err();
+    assert_eq!(err.msg, "The index already exists.");
+    assert_eq!(std::fs::read(&fai).unwrap(), b"old");
+
+    index_fasta(&fasta, &[("force", None)]).unwrap();
+    assert_eq!(std::fs::read(&fai).unwrap(), fixture("drAilAlti1.fa.fai"));
+}
+
+#[test]
+fn index_fasta_indexes_bgzf() {
+    let dir = temp_dir("index_fasta_indexes_bgzf");
+    let fasta = dir.join("drAilAlti1.fa.gz");
+    // small blocks, so the GZI index has entries.
+    let mut writer = noodles::bgzf::Writer::new(Vec::new());
+    for chunk in fixture("drAilAlti1.fa").chunks(4096) {
+        std::io::Write::write_all(&mut writer, chunk).unwrap();
+        std::io::Write::flush(&mut writer).unwrap();
+    }
+    std::fs::write(&fasta, writer.finish().unwrap()).unwrap();
+
+    index_fasta(&fasta, &[]).unwrap();
+    assert_eq!(
+        std::fs::read(dir.join("drAilAlti1.fa.gz.fai")).unwrap(),
+        fixture("drAilAlti1.fa.fai")
+    );
+    // an entry for each block after the first, after the count.
+    let gzi = std::fs::read(dir.join("drAilAlti1.fa.gz.gzi")).unwrap();
+    assert!(gzi.len() >= 8 + 4 * 16);
+    assert_eq!(
+        u64::from_le_bytes(gzi[..8].try_into().unwrap()) as usize,
+        (gzi.len() - 8) / 16
+    );
+
+    // the region spans two blocks.
+    let rows = run_with(
+        "bio faidx",
+        &call(&[fasta.to_str().unwrap(), "drAilAlti1:8150-8250"], &[]),
+        &Value::nothing(Span::unknown()),
+    );
+    assert_eq!(
+        rows.as_list().unwrap()[0]
+            .get_data_by_key("sequence")
+            .unwrap()
+            .coerce_string()
+            .unwrap(),
+        genome()[8149..8250]
+    );
+}
+
+#[test]
+fn from_fasta_reads_a_path() {
+    let path = fixture_path("drAilAlti1.fa");
+    let nothing = Value::nothing(Span::unknown());
+    assert_eq!(
+        format!(
+            "{:?}",
+            run_with(
+                "from fasta",
+                &call(&[path.to_str().unwrap()], &[]),
+                &nothing
+            )
+        ),
+        format!("{:?}", run("from fasta", &binary(fixture("drAilAlti1.fa"))))
+    );
+
+    let missing = fixture_path("missing.fa");
+    let err = try_run_with(
+        "from fasta",
+        &call(&[missing.to_str().unwrap()], &[]),
+        &nothing,
+    )
+    .unwrap_err();
+    assert_eq!(err.msg, format!("Could not open {}.", missing.display()));
+}
+
+#[test]
+fn from_fasta_reads_piped_bytes() {
+    let genome = fixture("drAilAlti1.fa");
+    let output = try_run_piped("from fasta", &call(&[], &[]), piped(&genome, 7)).unwrap();
+    assert!(matches!(output, PipelineData::ListStream(..)));
+    assert_eq!(
+        format!("{:?}", collect(output).unwrap()),
+        format!("{:?}", run("from fasta", &binary(genome)))
+    );
+}
+
+#[test]
+fn from_fastq_yields_records_before_the_input_ends() {
+    let endless = std::iter::repeat(b"@SEQ_ID\nGATT\n+\n!''*\n".to_vec());
+    let output = try_run_piped("from fastq", &call(&[], &[]), raw_stream(endless)).unwrap();
+    let first: Vec<_> = output.into_iter().take(3).collect();
+    assert_eq!(first.len(), 3);
+    for row in &first {
+        assert_eq!(
+            row.get_data_by_key("id").unwrap().coerce_string().unwrap(),
+            "SEQ_ID"
+        );
+    }
+}
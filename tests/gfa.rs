@@ -0,0 +1,28 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn to_gfa_round_trips_map_gfa() {
+    let gfa = String::from_utf8(fixture("map.gfa")).unwrap();
+    let out = text(&run("to gfa", &run("from gfa", &binary(gfa.clone()))));
+    assert_eq!(out, gfa);
+}
+
+#[test]
+fn to_gfa_writes_containments_and_paths() {
+    let gfa = "H\tVN:Z:1.0\n\
+        S\ta\tACGT\tLN:i:4\n\
+        S\tb\t*\n\
+        C\ta\t+\tb\t-\t2\t2M\n\
+        P\tp1\ta+,b-\t2M\n";
+    let out = text(&run("to gfa", &run("from gfa", &binary(gfa))));
+    assert_eq!(out, gfa);
+}
+
+#[test]
+fn to_gfa_round_trips_map_gfa_gz() {
+    let gfa = run("from gfa.gz", &binary(fixture("map.gfa.gz")));
+    let plain = run("from gfa", &binary(fixture("map.gfa")));
+    assert_eq!(text(&run("to gfa", &gfa)), text(&run("to gfa", &plain)));
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::*;
+use nu_protocol::{record, Span, Value};
+
+const GFF: &str = "##gff-version 3\n\
+    chr1\tsrc\tgene\t1\t100\t.\t+\t.\tID=gene1;Name=a%3Bb\n\
+    chr1\tsrc\tmRNA\t1\t100\t0.5\t+\t.\tID=m1;Parent=gene1\n\
+    chr1\tsrc\tCDS\t1\t99\t.\t+\t0\tParent=m1,gene1\n\
+    chr2\tsrc\tgene\t1\t10\t.\t-\t.\tID=gene2\n";
+
+#[test]
+fn to_gff_round_trips() {
+    let out = text(&run("to gff", &run("from gff", &binary(GFF))));
+    assert_eq!(out, GFF);
+}
+
+#[test]
+fn to_gff_writes_separators() {
+    let parsed = run("from gff", &binary(GFF));
+    let out = text(&run_with(
+        "to gff",
+        &call(&[], &[("separators", None)]),
+        &parsed,
+    ));
+    assert_eq!(out, GFF.replace("\nchr2", "\n###\nchr2"));
+}
+
+#[test]
+fn to_gff_writes_sequences() {
+    let sequences = run("from fasta", &binary(">chr1\nACGT\n"));
+    let input = Value::record(
+        record! {
+            "body" => run("from gff", &binary(GFF)),
+            "sequences" => sequences,
+        },
+        Span::unknown(),
+    );
+    let out = text(&run("to gff", &input));
+    assert_eq!(out, format!("{GFF}##FASTA\n>chr1\nACGT\n"));
+}
+
+const GFF_ATTRIBUTES: &str = "##gff-version 3\n\
+    sq0\tNOODLES\tgene\t8\t13\t.\t+\t.\tID=gene1;Note=a%3Bb;Dbxref=x:1,y:2\n";
+
+#[test]
+fn from_gff_parses_attributes() {
+    let rows = run("from gff", &binary(GFF_ATTRIBUTES));
+    let attributes = rows.as_list().unwrap()[0]
+        .get_data_by_key("attributes")
+        .unwrap();
+
+    assert_eq!(
+        attributes
+            .get_data_by_key("ID")
+            .unwrap()
+            .coerce_string()
+            .unwrap(),
+        "gene1"
+    );
+    // values are percent decoded, and lists split on commas.
+    assert_eq!(
+        attributes
+            .get_data_by_key("Note")
+            .unwrap()
+            .coerce_string()
+            .unwrap(),
+        "a;b"
+    );
+    let dbxref: Vec<String> = attributes
+        .get_data_by_key("Dbxref")
+        .unwrap()
+        .as_list()
+        .unwrap()
+        .iter()
+        .map(|v| v.coerce_string().unwrap())
+        .collect();
+    assert_eq!(dbxref, ["x:1", "y:2"]);
+
+    assert_eq!(text(&run("to gff", &rows)), GFF_ATTRIBUTES);
+}
+
+#[test]
+fn from_gff_raw_attributes() {
+    let raw = call(&[], &[("raw-attributes", None)]);
+    let rows = run_with("from gff", &raw, &binary(GFF_ATTRIBUTES));
+    let attributes = rows.as_list().unwrap()[0]
+        .get_data_by_key("attributes")
+        .unwrap();
+
+    assert_eq!(
+        attributes.coerce_string().unwrap(),
+        "ID=gene1;Note=a%3Bb;Dbxref=x:1,y:2"
+    );
+    assert_eq!(text(&run("to gff", &rows)), GFF_ATTRIBUTES);
+}
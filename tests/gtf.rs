@@ -0,0 +1,78 @@
+mod common;
+
+use common::*;
+use nu_protocol::Value;
+use std::io::Write;
+
+const GTF: &str = "#!genome-build GRCh38\n\
+    chr1\tHAVANA\tgene\t11869\t14409\t.\t+\t.\tgene_id \"ENSG1\"; gene_name \"DDX11L1\"; level 2;\n\
+    chr1\tHAVANA\texon\t11869\t12227\t.\t+\t.\tgene_id \"ENSG1\"; transcript_id \"ENST1\"; tag \"basic\"; tag \"a;b\";\n";
+
+fn attribute(row: &Value, key: &str) -> Value {
+    row.get_data_by_key("attributes")
+        .and_then(|a| a.get_data_by_key(key))
+        .unwrap_or_else(|| panic!("no attribute {key}"))
+}
+
+#[test]
+fn from_gtf_parses_attributes() {
+    let rows = run("from gtf", &binary(GTF));
+    let rows = rows.as_list().unwrap();
+    assert_eq!(rows.len(), 2);
+
+    assert_eq!(column(rows, "start")[0].as_int().unwrap(), 11869);
+    assert_eq!(
+        attribute(&rows[0], "gene_name").coerce_string().unwrap(),
+        "DDX11L1"
+    );
+    assert_eq!(
+        attribute(&rows[1], "transcript_id")
+            .coerce_string()
+            .unwrap(),
+        "ENST1"
+    );
+
+    // repeated keys are a list, and quoted values may hold a `;`.
+    let tags = attribute(&rows[1], "tag");
+    let tags: Vec<String> = tags
+        .as_list()
+        .unwrap()
+        .iter()
+        .map(|t| t.coerce_string().unwrap())
+        .collect();
+    assert_eq!(tags, ["basic", "a;b"]);
+}
+
+#[test]
+fn from_gtf_needs_a_transcript_id() {
+    let gtf = "chr1\tHAVANA\texon\t1\t2\t.\t+\t.\tgene_id \"ENSG1\";\n";
+    let err = try_run_with("from gtf", &call(&[], &[]), &binary(gtf)).unwrap_err();
+    assert_eq!(err.msg, "Invalid GTF record on line 1.");
+    assert!(
+        err.labels[0].text.contains("transcript_id"),
+        "{}",
+        err.labels[0].text
+    );
+}
+
+#[test]
+fn from_gtf_gz() {
+    let mut writer = noodles::bgzf::Writer::new(Vec::new());
+    writer.write_all(GTF.as_bytes()).unwrap();
+    let gz = writer.finish().unwrap();
+
+    assert_eq!(
+        format!("{:?}", run("from gtf.gz", &binary(gz))),
+        format!("{:?}", run("from gtf", &binary(GTF)))
+    );
+}
+
+#[test]
+fn from_gtf_gz_reads_plain_gzip() {
+    // as downloaded from GENCODE, which is gzip but not BGZF.
+    let gz = fixture("gencode.gtf.gz");
+    assert_eq!(
+        format!("{:?}", run("from gtf.gz", &binary(gz))),
+        format!("{:?}", run("from gtf", &binary(GTF)))
+    );
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use common::*;
+use nu_protocol::{Span, Value};
+
+/// The rows returned by querying a fixture for some regions.
+fn query(path: &str, regions: &[&str]) -> Vec<Value> {
+    let path = fixture_path(path);
+    let mut args = vec![path.to_str().unwrap()];
+    args.extend(regions);
+    body(&run_with(
+        "bio query",
+        &call(&args, &[]),
+        &Value::nothing(Span::unknown()),
+    ))
+}
+
+/// The read names of some BAM rows.
+fn read_names(rows: &[Value]) -> Vec<String> {
+    column(rows, "read_name")
+        .iter()
+        .map(|v| v.coerce_string().unwrap())
+        .collect()
+}
+
+#[test]
+fn query_bam_reads_only_the_region() {
+    let all = read_names(&body(&run("from bam", &binary(fixture("map_sorted.bam")))));
+    let region = read_names(&query("map_sorted.bam", &["drAilAlti1:1-20000"]));
+
+    assert!(!region.is_empty() && region.len() < all.len());
+    assert!(region.iter().all(|name| all.contains(name)));
+}
+
+#[test]
+fn query_bam_returns_records_once_for_overlapping_regions() {
+    let first = query("map_sorted.bam", &["drAilAlti1:1-20000"]);
+    let second = query("map_sorted.bam", &["drAilAlti1:10000-30000"]);
+    let both = query(
+        "map_sorted.bam",
+        &["drAilAlti1:1-20000", "drAilAlti1:10000-30000"],
+    );
+
+    // the regions share records, but each is only returned once.
+    assert!(read_names(&second)
+        .iter()
+        .any(|n| read_names(&first).contains(n)));
+    assert_eq!(
+        read_names(&both),
+        read_names(&query("map_sorted.bam", &["drAilAlti1:1-30000"]))
+    );
+}
+
+/// The positions of some VCF rows.
+fn positions(rows: &[Value]) -> Vec<i64> {
+    column(rows, "pos")
+        .iter()
+        .map(|v| v.as_int().unwrap())
+        .collect()
+}
+
+#[test]
+fn query_vcf_reads_only_the_region() {
+    let all = run("from vcf.gz", &binary(fixture("map.vcf.gz")));
+    let path = fixture_path("map.vcf.gz");
+    let region = run_with(
+        "bio query",
+        &call(&[path.to_str().unwrap(), "drAilAlti1:3000-12000"], &[]),
+        &Value::nothing(Span::unknown()),
+    );
+
+    assert_eq!(
+        format!("{:?}", region.get_data_by_key("header")),
+        format!("{:?}", all.get_data_by_key("header"))
+    );
+    // the rows are the same as from `from vcf.gz`.
+    let expected: Vec<_> = body(&all)
+        .into_iter()
+        .filter(|row| {
+            (3000..=12000).contains(&row.get_data_by_key("pos").unwrap().as_int().unwrap())
+        })
+        .collect();
+    assert_eq!(
+        positions(&body(&region)),
+        [3821, 3825, 6307, 8041, 11412, 11413]
+    );
+    assert_eq!(format!("{:?}", body(&region)), format!("{:?}", expected));
+}
+
+#[test]
+fn query_vcf_returns_records_once_for_overlapping_regions() {
+    let both = query(
+        "map.vcf.gz",
+        &["drAilAlti1:3000-12000", "drAilAlti1:11000-14000"],
+    );
+    assert_eq!(
+        positions(&both),
+        positions(&query("map.vcf.gz", &["drAilAlti1:3000-14000"]))
+    );
+}
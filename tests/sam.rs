@@ -0,0 +1,257 @@
+mod common;
+
+use common::*;
+use nu_protocol::{PipelineData, Span, Value};
+
+/// The header lines and the records. noodles writes the tags of a header
+/// line in its own order, so these are sorted. Float fields are written
+/// in their shortest form (`de:f:0.0010` becomes `de:f:0.001`), so these
+/// are normalised the same way.
+fn split_sam(sam: &str) -> (Vec<Vec<&str>>, Vec<String>) {
+    let (header, body): (Vec<&str>, Vec<&str>) = sam.lines().partition(|l| l.starts_with('@'));
+    let header = header
+        .iter()
+        .map(|l| {
+            let mut tags: Vec<_> = l.split('\t').collect();
+            tags[1..].sort();
+            tags
+        })
+        .collect();
+    let body = body
+        .iter()
+        .map(|l| {
+            l.split('\t')
+                .map(|field| match field.get(2..5) {
+                    Some(":f:") => format!("{}{}", &field[..5], field[5..].parse::<f32>().unwrap()),
+                    _ => field.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("\t")
+        })
+        .collect();
+    (header, body)
+}
+
+#[test]
+fn to_sam_round_trips_map_sam() {
+    let sam = String::from_utf8(fixture("map.sam")).unwrap();
+    let out = text(&run("to sam", &run("from sam", &binary(sam.clone()))));
+
+    // the header has no @HD line, and none is added.
+    assert_eq!(split_sam(&out), split_sam(&sam));
+}
+
+#[test]
+fn to_bam_round_trips_map_bam() {
+    let bam = run("from bam", &binary(fixture("map.bam")));
+    let sam = text(&run("to sam", &bam));
+
+    let again = run("from bam", &run("to bam", &bam));
+    assert_eq!(text(&run("to sam", &again)), sam);
+
+    // the BAM holds the same alignments as the SAM.
+    let (_, body) = split_sam(&sam);
+    assert_eq!(
+        body,
+        split_sam(&String::from_utf8(fixture("map.sam")).unwrap()).1
+    );
+}
+
+#[test]
+fn to_bam_ends_with_the_bgzf_eof_marker() {
+    let bam = run("to bam", &run("from sam", &binary(fixture("map.sam"))));
+    let bytes = bam.as_binary().unwrap();
+    assert!(bytes.ends_with(&[
+        0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02,
+        0x00, 0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ]));
+}
+
+#[test]
+fn to_sam_keeps_only_the_hd_fields_present() {
+    let sam = "@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:100\nr1\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t*\n";
+    let out = text(&run("to sam", &run("from sam", &binary(sam))));
+    assert_eq!(out, sam);
+}
+
+#[test]
+fn to_bam_keeps_the_header() {
+    let sam = run("from sam", &binary(fixture("map.sam")));
+    let bam = run("from bam", &run("to bam", &sam));
+    assert_eq!(
+        format!("{:?}", bam.get_data_by_key("header")),
+        format!("{:?}", sam.get_data_by_key("header"))
+    );
+    assert_eq!(format!("{:?}", body(&bam)), format!("{:?}", body(&sam)));
+}
+
+#[test]
+fn to_bam_needs_a_header_and_body() {
+    let err = try_run_with("to bam", &call(&[], &[]), &binary("not a record")).unwrap_err();
+    assert_eq!(
+        err.msg,
+        "Input should be a record with a header and a body."
+    );
+}
+
+#[test]
+fn decode_flags() {
+    let decode = call(&[], &[("decode-flags", None)]);
+    let sam = run_with("from sam", &decode, &binary(fixture("map.sam")));
+    let rows = body(&sam);
+
+    assert_eq!(
+        &rows[0]
+            .as_record()
+            .unwrap()
+            .columns()
+            .cloned()
+            .collect::<Vec<_>>()[..3],
+        ["read_name", "flags", "decoded_flags"]
+    );
+
+    let unmapped = rows
+        .iter()
+        .find(|r| r.get_data_by_key("flags").unwrap().as_int().unwrap() == 4)
+        .unwrap();
+    let decoded = unmapped.get_data_by_key("decoded_flags").unwrap();
+    let flag = |name: &str| decoded.get_data_by_key(name).unwrap().as_bool().unwrap();
+    assert!(flag("unmapped"));
+    assert!(!flag("reverse") && !flag("paired") && !flag("secondary"));
+
+    // decoded flags are only for reading, `to sam` writes the integer.
+    let plain = run("from sam", &binary(fixture("map.sam")));
+    assert_eq!(text(&run("to sam", &sam)), text(&run("to sam", &plain)));
+}
+
+#[test]
+fn typed_columns() {
+    let sam = run("from sam", &binary(fixture("map.sam")));
+    let rows = body(&sam);
+    let get = |row: usize, col: &str| rows[row].get_data_by_key(col).unwrap();
+
+    // sequence-2  0  drAilAlti1  15440  60  932M3D1M3D1076M3D1M3D1264M  *  0  0
+    assert_eq!(
+        get(1, "reference_sequence_name").coerce_string().unwrap(),
+        "drAilAlti1"
+    );
+    assert_eq!(get(1, "alignment_start").as_int().unwrap(), 15440);
+    assert_eq!(get(1, "mapping_quality").as_int().unwrap(), 60);
+    assert!(get(1, "mate_reference_sequence_name").is_nothing());
+    assert!(get(1, "mate_alignment_start").is_nothing());
+    assert_eq!(get(1, "template_length").as_int().unwrap(), 0);
+
+    let cigar = get(1, "cigar");
+    let ops: Vec<(String, i64)> = cigar
+        .as_list()
+        .unwrap()
+        .iter()
+        .map(|op| {
+            (
+                op.get_data_by_key("op").unwrap().coerce_string().unwrap(),
+                op.get_data_by_key("len").unwrap().as_int().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        ops[..3],
+        [("M".into(), 932), ("D".into(), 3), ("M".into(), 1)]
+    );
+
+    // sequence-24 is unmapped.
+    let unmapped = rows
+        .iter()
+        .position(|r| {
+            r.get_data_by_key("read_name")
+                .unwrap()
+                .coerce_string()
+                .unwrap()
+                == "sequence-24"
+        })
+        .unwrap();
+    assert!(get(unmapped, "reference_sequence_name").is_nothing());
+    assert!(get(unmapped, "alignment_start").is_nothing());
+    assert!(get(unmapped, "cigar").as_list().unwrap().is_empty());
+}
+
+#[test]
+fn to_sam_takes_a_cigar_string() {
+    let sam = "@SQ\tSN:chr1\tLN:100\nr1\t0\tchr1\t5\t30\t2M1I1M\t*\t0\t0\tACGT\t*\n";
+    let mut parsed = run("from sam", &binary(sam));
+    if let Value::Record { val, .. } = &mut parsed {
+        let body = val.get_mut("body").unwrap();
+        if let Value::List { vals, .. } = body {
+            if let Value::Record { val: row, .. } = &mut vals[0] {
+                *row.get_mut("cigar").unwrap() = Value::string("2M1I1M", Span::unknown());
+            }
+        }
+    }
+    assert_eq!(text(&run("to sam", &parsed)), sam);
+}
+
+#[test]
+fn data_keeps_its_types() {
+    let sam = "@SQ\tSN:chr1\tLN:100\n\
+        r1\t0\tchr1\t5\t30\t4M\t*\t0\t0\tACGT\t*\ttp:A:P\tXH:H:1AE3\tNM:i:-2\tXf:f:0.5\tXZ:Z:a b\tXB:B:s,-1,300\n";
+    let parsed = run("from sam", &binary(sam));
+    assert_eq!(text(&run("to sam", &parsed)), sam);
+
+    let types = body(&parsed)[0].get_data_by_key("data_types").unwrap();
+    let ty = |tag: &str| types.get_data_by_key(tag).unwrap().coerce_string().unwrap();
+    assert_eq!(ty("tp"), "A");
+    assert_eq!(ty("XH"), "H");
+    assert_eq!(ty("XB"), "B:s");
+
+    // BAM keeps integer widths and signedness.
+    let bam = run("from bam", &binary(fixture("map.bam")));
+    let again = run("from bam", &run("to bam", &bam));
+    assert_eq!(
+        format!("{:?}", column(&body(&again), "data_types")),
+        format!("{:?}", column(&body(&bam), "data_types"))
+    );
+}
+
+#[test]
+fn data_types_are_checked() {
+    let sam = "@SQ\tSN:chr1\tLN:100\nr1\t0\tchr1\t5\t30\t4M\t*\t0\t0\tACGT\t*\tXc:i:-2\n";
+    let mut parsed = run("from sam", &binary(sam));
+    if let Value::Record { val, .. } = &mut parsed {
+        if let Value::List { vals, .. } = val.get_mut("body").unwrap() {
+            if let Value::Record { val: row, .. } = &mut vals[0] {
+                *row.get_mut("data_types").unwrap() = Value::record(
+                    nu_protocol::record! { "Xc" => Value::string("C", Span::unknown()) },
+                    Span::unknown(),
+                );
+            }
+        }
+    }
+    let err = try_run_with("to sam", &call(&[], &[]), &parsed).unwrap_err();
+    assert_eq!(err.msg, "Could not parse tag `Xc` in column `data`.");
+    assert_eq!(err.labels[0].text, "-2 does not fit in type C");
+}
+
+#[test]
+fn from_bam_reads_a_path() {
+    let path = fixture_path("map.bam");
+    let from_path = run_with(
+        "from bam",
+        &call(&[path.to_str().unwrap()], &[]),
+        &Value::nothing(Span::unknown()),
+    );
+    assert_eq!(
+        format!("{:?}", from_path),
+        format!("{:?}", run("from bam", &binary(fixture("map.bam"))))
+    );
+}
+
+#[test]
+fn from_bam_body_streams_the_records() {
+    let bam = fixture("map.bam");
+    let output =
+        try_run_piped("from bam", &call(&[], &[("body", None)]), piped(&bam, 100)).unwrap();
+    assert!(matches!(output, PipelineData::ListStream(..)));
+    assert_eq!(
+        format!("{:?}", collect(output).unwrap().as_list().unwrap()),
+        format!("{:?}", body(&run("from bam", &binary(bam))))
+    );
+}
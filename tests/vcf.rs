@@ -0,0 +1,295 @@
+mod common;
+
+use common::*;
+use nu_protocol::{PipelineData, Span, Value};
+
+/// The `##` lines of a VCF, sorted as noodles writes them in its own
+/// order, and the rest.
+fn split_vcf(vcf: &str) -> (Vec<&str>, Vec<&str>) {
+    let (mut meta, rest): (Vec<&str>, Vec<&str>) = vcf.lines().partition(|l| l.starts_with("##"));
+    meta.sort();
+    (meta, rest)
+}
+
+fn map_vcf() -> String {
+    let mut vcf = String::new();
+    std::io::Read::read_to_string(
+        &mut noodles::bgzf::Reader::new(fixture("map.vcf.gz").as_slice()),
+        &mut vcf,
+    )
+    .unwrap();
+    vcf
+}
+
+#[test]
+fn to_vcf_round_trips_map_vcf() {
+    let vcf = map_vcf();
+    let out = text(&run(
+        "to vcf",
+        &run("from vcf.gz", &binary(fixture("map.vcf.gz"))),
+    ));
+    assert_eq!(split_vcf(&out), split_vcf(&vcf));
+}
+
+#[test]
+fn to_vcf_gz_round_trips_map_vcf() {
+    let vcf = run("from vcf.gz", &binary(fixture("map.vcf.gz")));
+    let gz = run("to vcf.gz", &vcf);
+    assert_eq!(
+        text(&run("to vcf", &run("from vcf.gz", &gz))),
+        text(&run("to vcf", &vcf))
+    );
+}
+
+#[test]
+fn to_vcf_keeps_other_header_records() {
+    let vcf = "##fileformat=VCFv4.3\n\
+        ##source=test\n\
+        ##SAMPLE=<ID=s1,Assay=\"WholeGenome\">\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n";
+    let parsed = run("from vcf", &binary(vcf));
+
+    let other = parsed.get_data_by_key("header").unwrap();
+    let other = other.get_data_by_key("other").unwrap();
+    assert_eq!(
+        text(&other.get_data_by_key("source").unwrap().as_list().unwrap()[0]),
+        "test"
+    );
+
+    let out = text(&run("to vcf", &parsed));
+    assert!(out.contains("##source=test\n"), "{out}");
+    assert!(
+        out.contains("##SAMPLE=<ID=s1,Assay=\"WholeGenome\">\n"),
+        "{out}"
+    );
+}
+
+#[test]
+fn to_vcf_writes_missing_samples_as_a_dot() {
+    let vcf = "##fileformat=VCFv4.3\n\
+        ##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n\
+        ##contig=<ID=chr1,length=100>\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1\ts2\n\
+        chr1\t9\t.\tG\tT\t.\t.\t.\tGT\t.\t1|1\n";
+    let out = text(&run("to vcf", &run("from vcf", &binary(vcf))));
+    assert_eq!(out, vcf);
+}
+
+/// Three samples, with missing genotypes.
+const MISSING_GT_VCF: &str = "##fileformat=VCFv4.3\n\
+    ##contig=<ID=chr1,length=100>\n\
+    ##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n\
+    ##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Read depth\">\n\
+    #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1\ts2\ts3\n\
+    chr1\t5\t.\tA\tC\t.\t.\t.\tGT:DP\t0/1:3\t.:.\t./.:4\n\
+    chr1\t9\t.\tG\tT\t.\t.\t.\tGT\t.\t1|1\t0\n";
+
+#[test]
+fn to_bcf_writes_missing_genotypes() {
+    let vcf = run("from vcf", &binary(MISSING_GT_VCF));
+    let bcf = run("from bcf", &run("to bcf", &vcf));
+    let out = text(&run("to vcf", &bcf));
+    assert!(out.contains("\t0/1:3\t.:.\t./.:4\n"), "{out}");
+    assert!(out.contains("\t.\t1|1\t0\n"), "{out}");
+    assert_eq!(out, text(&run("to vcf", &vcf)));
+}
+
+#[test]
+fn to_bcf_round_trips_map_bcf() {
+    let bcf = run("from bcf", &binary(fixture("map.bcf")));
+    let again = run("from bcf", &run("to bcf", &bcf));
+    assert_eq!(text(&run("to vcf", &again)), text(&run("to vcf", &bcf)));
+}
+
+#[test]
+fn to_bcf_rejects_mixed_ploidy() {
+    let vcf = MISSING_GT_VCF.replace("1|1\t0\n", "1|1\t0/1/1\n");
+    let err = try_run_with("to bcf", &call(&[], &[]), &run("from vcf", &binary(vcf))).unwrap_err();
+    assert_eq!(
+        err.msg,
+        "Genotypes of mixed ploidy can not be written to BCF."
+    );
+    assert!(
+        err.labels[0].text.contains("chr1:9") && err.labels[0].text.contains("sample s2"),
+        "{}",
+        err.labels[0].text
+    );
+}
+
+#[test]
+fn from_vcf_types_info_by_header() {
+    let vcf = run("from vcf.gz", &binary(fixture("map.vcf.gz")));
+    let info = body(&vcf)[0].get_data_by_key("info").unwrap();
+    let get = |key: &str| info.get_data_by_key(key).unwrap();
+
+    // INDEL;IDV=4;IMF=0.8;...;AC=1;AN=2;DP4=1,0,4,0
+    assert!(get("INDEL").as_bool().unwrap());
+    assert_eq!(get("IDV").as_int().unwrap(), 4);
+    assert_eq!(get("IMF").as_f64().unwrap(), 0.8);
+    let list = |key: &str| -> Vec<i64> {
+        get(key)
+            .as_list()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_int().unwrap())
+            .collect()
+    };
+    assert_eq!(list("DP4"), [1, 0, 4, 0]);
+    // Number=A is a list, even with one alternate allele.
+    assert_eq!(list("AC"), [1]);
+
+    // BCF gives the same types.
+    let bcf = run("from bcf", &binary(fixture("map.bcf")));
+    assert_eq!(
+        format!("{:?}", body(&bcf)[0].get_data_by_key("info")),
+        format!("{:?}", Some(info.clone()))
+    );
+}
+
+/// The GT of a sample of a record.
+fn gt(vcf: &Value, row: usize, sample: &str) -> Value {
+    body(vcf)[row]
+        .get_data_by_key("genotypes")
+        .and_then(|g| g.get_data_by_key(sample))
+        .and_then(|s| s.get_data_by_key("GT"))
+        .unwrap()
+}
+
+fn alleles(gt: &Value) -> Vec<Option<i64>> {
+    gt.get_data_by_key("alleles")
+        .unwrap()
+        .as_list()
+        .unwrap()
+        .iter()
+        .map(|a| a.as_int().ok())
+        .collect()
+}
+
+#[test]
+fn from_vcf_parses_genotypes() {
+    let vcf = run("from vcf", &binary(MISSING_GT_VCF));
+
+    // 0/1, unphased.
+    let het = gt(&vcf, 0, "s1");
+    assert_eq!(alleles(&het), [Some(0), Some(1)]);
+    assert!(!het.get_data_by_key("phased").unwrap().as_bool().unwrap());
+
+    // ./., both alleles missing.
+    let missing = gt(&vcf, 0, "s3");
+    assert_eq!(alleles(&missing), [None, None]);
+    assert!(!missing
+        .get_data_by_key("phased")
+        .unwrap()
+        .as_bool()
+        .unwrap());
+
+    // 1|1, phased.
+    let phased = gt(&vcf, 1, "s2");
+    assert_eq!(alleles(&phased), [Some(1), Some(1)]);
+    assert!(phased.get_data_by_key("phased").unwrap().as_bool().unwrap());
+
+    // haploid 0 has no phasing.
+    let haploid = gt(&vcf, 1, "s3");
+    assert_eq!(alleles(&haploid), [Some(0)]);
+    assert!(haploid.get_data_by_key("phased").unwrap().is_nothing());
+}
+
+#[test]
+fn from_vcf_missing_sample_genotypes() {
+    let vcf = run("from vcf", &binary(MISSING_GT_VCF));
+
+    // `.:.` and a bare `.` are missing GT values.
+    assert!(gt(&vcf, 0, "s2").is_nothing());
+    assert!(gt(&vcf, 1, "s1").is_nothing());
+}
+
+#[test]
+fn vcf_long_melts_samples() {
+    let vcf = run("from vcf", &binary(MISSING_GT_VCF));
+    let long = run("bio vcf long", &vcf);
+    let rows = long.as_list().unwrap();
+
+    // two records of three samples.
+    assert_eq!(rows.len(), 6);
+    assert_eq!(
+        rows[0]
+            .as_record()
+            .unwrap()
+            .columns()
+            .cloned()
+            .collect::<Vec<_>>(),
+        ["chrom", "pos", "ref", "alt", "sample", "GT", "DP"]
+    );
+    let samples: Vec<String> = column(rows, "sample")
+        .iter()
+        .map(|s| s.coerce_string().unwrap())
+        .collect();
+    assert_eq!(samples, ["s1", "s2", "s3", "s1", "s2", "s3"]);
+    assert_eq!(column(rows, "pos")[3].as_int().unwrap(), 9);
+    assert_eq!(column(rows, "DP")[2].as_int().unwrap(), 4);
+    // the second record has no DP.
+    assert!(column(rows, "DP")[4].is_nothing());
+
+    // the body alone works too.
+    let body = vcf.get_data_by_key("body").unwrap();
+    assert_eq!(
+        format!("{:?}", run("bio vcf long", &body)),
+        format!("{long:?}")
+    );
+}
+
+#[test]
+fn vcf_long_map_vcf() {
+    let vcf = run("from vcf.gz", &binary(fixture("map.vcf.gz")));
+    let long = run("bio vcf long", &vcf);
+    assert_eq!(long.as_list().unwrap().len(), body(&vcf).len());
+}
+
+#[test]
+fn from_vcf_gz_reads_a_path() {
+    let path = fixture_path("map.vcf.gz");
+    let from_path = run_with(
+        "from vcf.gz",
+        &call(&[path.to_str().unwrap()], &[]),
+        &Value::nothing(Span::unknown()),
+    );
+    assert_eq!(
+        format!("{:?}", from_path),
+        format!("{:?}", run("from vcf.gz", &binary(fixture("map.vcf.gz"))))
+    );
+}
+
+#[test]
+fn from_vcf_and_bcf_report_bad_input() {
+    let vcf = "##fileformat=VCFv4.3\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+        sq0\tnot-a-position\t.\tA\tC\t.\tPASS\t.\n";
+    let err = try_run_with("from vcf", &call(&[], &[]), &binary(vcf)).unwrap_err();
+    assert_eq!(err.msg, "Record reading failed.");
+
+    let err = try_run_with("from bcf", &call(&[], &[]), &binary(vcf)).unwrap_err();
+    assert!(err.msg.contains("header"), "{}", err.msg);
+}
+
+#[test]
+fn from_vcf_body_streams_the_records() {
+    let vcf = map_vcf().into_bytes();
+    let output =
+        try_run_piped("from vcf", &call(&[], &[("body", None)]), piped(&vcf, 100)).unwrap();
+    assert!(matches!(output, PipelineData::ListStream(..)));
+    assert_eq!(
+        format!("{:?}", collect(output).unwrap().as_list().unwrap()),
+        format!("{:?}", body(&run("from vcf", &binary(vcf))))
+    );
+
+    let bad = "##fileformat=VCFv4.3\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+        sq0\t1\t.\tA\tC\t.\tPASS\t.\nsq0\tnot-a-position\t.\tA\tC\t.\tPASS\t.\n";
+    let output = try_run_piped(
+        "from vcf",
+        &call(&[], &[("body", None)]),
+        piped(bad.as_bytes(), 100),
+    );
+    assert_eq!(
+        collect(output.unwrap()).unwrap_err().msg,
+        "Record reading failed."
+    );
+}